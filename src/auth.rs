@@ -1,9 +1,14 @@
+use crate::paths::state_dir;
 use crate::{Result, UklientError, CLIENT};
-use chrono::{Duration, Utc};
+use chrono::{DateTime, Duration, Utc};
 use serde::{Deserialize, Serialize};
 use serde_json::{Map, Value};
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
 use theseus::prelude::Credentials;
 use tokio::time::interval;
+use tracing::warn;
 
 const CODE_URL: &str =
     "https://login.microsoftonline.com/consumers/oauth2/v2.0/devicecode";
@@ -24,6 +29,75 @@ pub struct DeviceCode {
     pub user_code: String,
     pub device_code: String,
     pub verification_uri: String,
+    /// How many seconds the code can be used for
+    pub expires_in: i64,
+}
+
+/// A device code the user may still be entering, kept so that a restarted
+/// login keeps polling it instead of asking for a new one
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PendingLogin {
+    pub code: DeviceCode,
+    pub expires: DateTime<Utc>,
+}
+
+impl PendingLogin {
+    pub fn new(code: DeviceCode) -> Self {
+        let expires = Utc::now() + Duration::seconds(code.expires_in);
+        Self { code, expires }
+    }
+
+    /// Reads the pending login, if there is one that hasn't expired yet
+    pub fn load() -> Result<Option<Self>> {
+        Self::load_file(&pending_login_path())
+    }
+
+    /// Reads the pending login in `path`, deleting it if it has expired or
+    /// can't be read so that a new login is started instead
+    fn load_file(path: &Path) -> Result<Option<Self>> {
+        if !path.try_exists()? {
+            return Ok(None);
+        }
+
+        let file = File::open(path)?;
+        let pending =
+            match serde_json::from_reader::<_, Self>(BufReader::new(file)) {
+                Ok(pending) if pending.expires > Utc::now() => Some(pending),
+                Ok(_) => None,
+                Err(e) => {
+                    warn!("Ignoring the unreadable pending login: {e}");
+                    None
+                }
+            };
+        if pending.is_none() {
+            std::fs::remove_file(path)?;
+        }
+
+        Ok(pending)
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let path = pending_login_path();
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let file = File::create(path)?;
+        serde_json::to_writer(BufWriter::new(file), self)?;
+        Ok(())
+    }
+
+    pub fn clear() -> Result<()> {
+        let path = pending_login_path();
+        if path.try_exists()? {
+            std::fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+}
+
+fn pending_login_path() -> PathBuf {
+    state_dir().join("pending-login.json")
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -232,3 +306,18 @@ pub async fn refresh_credentials(creds: Credentials) -> Result<Credentials> {
 // 3. get mojang services token
 // 4. get yggdrasil token
 // 5. fetch profile info
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn corrupt_pending_login_is_deleted() {
+        let path = std::env::temp_dir()
+            .join(format!("uklient-pending-{}.json", std::process::id()));
+        std::fs::write(&path, "{\"code\": ").unwrap();
+
+        assert!(PendingLogin::load_file(&path).unwrap().is_none());
+        assert!(!path.exists());
+    }
+}
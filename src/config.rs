@@ -0,0 +1,71 @@
+use crate::loader::Loader;
+use crate::paths::config_path;
+use crate::{Result, UklientError};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+use theseus::data::WindowSize;
+
+/// Settings read from a config file, the command line takes precedence
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Config {
+    pub modpack_id: Option<String>,
+    pub game_version: Option<String>,
+    pub max_memory: Option<u32>,
+    /// Used instead of 4096 MB when a profile has no memory set yet
+    pub default_memory_mb: Option<u32>,
+    pub loader: Option<Loader>,
+    /// Used when neither the modpack nor the command line gives a loader
+    pub default_loader: Option<Loader>,
+    pub modrinth_api_url: Option<String>,
+    /// The minimum heap size of the game, in MB
+    pub min_memory: Option<u32>,
+    /// The size of the game window, as `[width, height]`
+    pub resolution: Option<WindowSize>,
+    pub fullscreen: Option<bool>,
+    /// Settings of single modpacks by id, over the ones above
+    #[serde(default)]
+    pub profiles: HashMap<String, ProfileConfig>,
+}
+
+/// The settings that can be changed for a single modpack
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ProfileConfig {
+    pub max_memory: Option<u32>,
+    pub min_memory: Option<u32>,
+    pub resolution: Option<WindowSize>,
+    pub fullscreen: Option<bool>,
+}
+
+impl Config {
+    /// Reads the config at `path`, which has to exist
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.is_file() {
+            return Err(UklientError::ConfigNotFound(path.into()));
+        }
+
+        Ok(toml::from_str(&std::fs::read_to_string(path)?)?)
+    }
+
+    /// The settings of `modpack_id`, falling back to the global ones
+    pub fn profile(&self, modpack_id: &str) -> ProfileConfig {
+        let profile =
+            self.profiles.get(modpack_id).cloned().unwrap_or_default();
+        ProfileConfig {
+            max_memory: profile.max_memory.or(self.max_memory),
+            min_memory: profile.min_memory.or(self.min_memory),
+            resolution: profile.resolution.or(self.resolution),
+            fullscreen: profile.fullscreen.or(self.fullscreen),
+        }
+    }
+
+    /// Reads the default config, if there is one
+    pub fn load_default() -> Result<Self> {
+        let path = config_path();
+        if path.try_exists()? {
+            Self::load(&path)
+        } else {
+            Ok(Self::default())
+        }
+    }
+}
@@ -0,0 +1,129 @@
+use crate::{run_profile, Result, UklientError};
+use clap::ValueEnum;
+use std::path::Path;
+use theseus::auth::Credentials;
+use theseus::data::Hooks;
+use theseus::profile;
+use tokio::process::Child;
+use tracing::{info, warn};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Gpu {
+    Integrated,
+    Discrete,
+}
+
+const NVIDIA_PRIME_VARS: &[(&str, &str)] = &[
+    ("__NV_PRIME_RENDER_OFFLOAD", "1"),
+    ("__GLX_VENDOR_LIBRARY_NAME", "nvidia"),
+    ("__VK_LAYER_NV_optimus", "NVIDIA_only"),
+];
+
+/// The wrapper script setting the GPU variables, in the profile directory
+const GPU_WRAPPER: &str = "uklient-gpu.sh";
+
+/// The environment variables picking `gpu` on hybrid graphics setups, `None`
+/// meaning that the variable is removed
+pub fn gpu_env(gpu: Gpu) -> Vec<(&'static str, Option<&'static str>)> {
+    match gpu {
+        Gpu::Integrated => std::iter::once(("DRI_PRIME", Some("0")))
+            .chain(NVIDIA_PRIME_VARS.iter().map(|(key, _)| (*key, None)))
+            .collect(),
+        Gpu::Discrete => std::iter::once(("DRI_PRIME", Some("1")))
+            .chain(
+                NVIDIA_PRIME_VARS
+                    .iter()
+                    .map(|(key, value)| (*key, Some(*value))),
+            )
+            .collect(),
+    }
+}
+
+/// Launches a profile with the environment picking `gpu`, theseus having no
+/// setting for it: the game is started through a script setting the
+/// variables, used as the wrapper hook of this launch only
+pub async fn run_with_gpu(
+    path: &Path,
+    credentials: &Credentials,
+    gpu: Gpu,
+) -> Result<Child> {
+    if !cfg!(target_os = "linux") {
+        warn!("--gpu is only supported on Linux, ignoring it");
+        return run_profile(path, credentials).await;
+    }
+
+    let hooks = profile::get(path)
+        .await?
+        .ok_or_else(|| UklientError::ProfileNotRegistered(path.into()))?
+        .hooks;
+    let user_wrapper = hooks.as_ref().and_then(|h| h.wrapper.clone());
+    let script = path.join(GPU_WRAPPER);
+    std::fs::write(&script, wrapper_script(gpu, user_wrapper.as_deref()))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(
+            &script,
+            std::fs::Permissions::from_mode(0o755),
+        )?;
+    }
+
+    set_wrapper(path, Some(script.display().to_string())).await?;
+    let child = run_profile(path, credentials).await;
+    set_wrapper(path, user_wrapper).await?;
+    info!("Using the {gpu:?} GPU");
+    child
+}
+
+async fn set_wrapper(path: &Path, wrapper: Option<String>) -> Result<()> {
+    profile::edit(path, |p| {
+        p.hooks.get_or_insert_with(Hooks::default).wrapper = wrapper.clone();
+        async { Ok(()) }
+    })
+    .await?;
+    Ok(())
+}
+
+/// A script setting the variables picking `gpu` before running its
+/// arguments, through the user's own wrapper if there is one
+fn wrapper_script(gpu: Gpu, user_wrapper: Option<&str>) -> String {
+    let quote = |s: &str| format!("'{}'", s.replace('\'', r"'\''"));
+    let mut script = String::from("#!/bin/sh\n");
+    for (key, value) in gpu_env(gpu) {
+        match value {
+            Some(value) => {
+                script.push_str(&format!("export {key}={}\n", quote(value)))
+            }
+            None => script.push_str(&format!("unset {key}\n")),
+        }
+    }
+    match user_wrapper {
+        Some(wrapper) => {
+            script.push_str(&format!("exec {} \"$@\"\n", quote(wrapper)))
+        }
+        None => script.push_str("exec \"$@\"\n"),
+    }
+    script
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn integrated_gpu_unsets_the_nvidia_variables() {
+        let script = wrapper_script(Gpu::Integrated, None);
+        assert!(script.contains("export DRI_PRIME='0'\n"));
+        assert!(script.contains("unset __NV_PRIME_RENDER_OFFLOAD\n"));
+        assert!(script.ends_with("exec \"$@\"\n"));
+    }
+
+    #[test]
+    fn discrete_gpu_keeps_the_user_wrapper() {
+        let script =
+            wrapper_script(Gpu::Discrete, Some("/usr/bin/gamemoderun"));
+        assert!(script.contains("export DRI_PRIME='1'\n"));
+        assert!(script.contains("export __GLX_VENDOR_LIBRARY_NAME='nvidia'\n"));
+        assert!(script.ends_with("exec '/usr/bin/gamemoderun' \"$@\"\n"));
+    }
+}
@@ -0,0 +1,109 @@
+use crate::paths::{check_modpack_id, state_dir};
+use crate::Result;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::PathBuf;
+
+/// How many installed versions are remembered for each modpack
+const MAX_HISTORY: usize = 5;
+
+/// The modpack versions that were installed, oldest first
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct History {
+    pub versions: Vec<String>,
+}
+
+impl History {
+    pub fn load(modpack_id: &str) -> Result<Self> {
+        let path = history_path(modpack_id)?;
+        if !path.try_exists()? {
+            return Ok(Self::default());
+        }
+
+        let file = File::open(path)?;
+        Ok(serde_json::from_reader(BufReader::new(file))?)
+    }
+
+    pub fn save(&self, modpack_id: &str) -> Result<()> {
+        let path = history_path(modpack_id)?;
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let file = File::create(path)?;
+        serde_json::to_writer(BufWriter::new(file), self)?;
+        Ok(())
+    }
+
+    /// Records `version_id` as the currently installed version
+    pub fn push(&mut self, version_id: String) {
+        if self.versions.last() == Some(&version_id) {
+            return;
+        }
+
+        self.versions.push(version_id);
+        if self.versions.len() > MAX_HISTORY {
+            self.versions.remove(0);
+        }
+    }
+
+    /// Forgets the currently installed version and returns the one before it
+    pub fn rollback(&mut self) -> Option<String> {
+        self.versions.pop();
+        self.versions.last().cloned()
+    }
+}
+
+fn history_path(modpack_id: &str) -> Result<PathBuf> {
+    check_modpack_id(modpack_id)?;
+    Ok(state_dir()
+        .join("history")
+        .join(format!("{modpack_id}.json")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history(versions: &[&str]) -> History {
+        History {
+            versions: versions.iter().map(|v| v.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn rollback_returns_the_previous_version() {
+        let mut history = history(&["a", "b", "c"]);
+
+        assert_eq!(history.rollback().as_deref(), Some("b"));
+        assert_eq!(history.versions, ["a", "b"]);
+        assert_eq!(history.rollback().as_deref(), Some("a"));
+        assert_eq!(history.rollback(), None);
+    }
+
+    #[test]
+    fn push_skips_the_current_version_and_forgets_the_oldest() {
+        let mut history = history(&["1", "2", "3", "4", "5"]);
+        history.push("5".into());
+        assert_eq!(history.versions.len(), MAX_HISTORY);
+
+        history.push("6".into());
+        assert_eq!(history.versions, ["2", "3", "4", "5", "6"]);
+    }
+
+    #[test]
+    fn dotted_ids_have_their_own_file() {
+        assert!(history_path("my.pack").unwrap().ends_with("my.pack.json"));
+        assert_ne!(
+            history_path("foo.a").unwrap(),
+            history_path("foo.b").unwrap()
+        );
+    }
+
+    #[test]
+    fn ids_with_paths_have_no_history() {
+        assert!(history_path("../state").is_err());
+        assert!(history_path("packs/ukupvp").is_err());
+    }
+}
@@ -0,0 +1,215 @@
+use crate::{Result, CLIENT};
+use libium::upgrade::Downloadable;
+use once_cell::sync::OnceCell;
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use reqwest::{RequestBuilder, Response, StatusCode};
+use std::num::NonZeroU32;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::fs::{rename, File};
+use tokio::io::AsyncWriteExt;
+use tracing::warn;
+use url::Url;
+
+/// How many times a rate limited request is retried before giving up
+const MAX_RETRIES: u32 = 3;
+/// How long to wait when the server doesn't say when to retry
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(5);
+
+static THROTTLE: OnceCell<Mutex<TokenBucket>> = OnceCell::new();
+
+/// Shares a download rate between all downloads, allowing bursts of up to a
+/// second worth of data
+#[derive(Debug)]
+struct TokenBucket {
+    /// Bytes per second
+    rate: f64,
+    /// Bytes that can be downloaded right away, negative when in debt
+    available: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    /// Takes `bytes` from the bucket, returning how long to wait before the
+    /// bucket is no longer in debt
+    fn take(&mut self, bytes: usize) -> Duration {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.available = (self.available + elapsed * self.rate).min(self.rate);
+        self.last_refill = now;
+        self.available -= bytes as f64;
+
+        if self.available >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.available / self.rate)
+        }
+    }
+}
+
+/// Limits the combined speed of all downloads for the rest of the run
+pub fn set_max_download_rate(kb_per_sec: NonZeroU32) {
+    let rate = f64::from(kb_per_sec.get()) * 1024.0;
+    let _ = THROTTLE.set(Mutex::new(TokenBucket {
+        rate,
+        available: rate,
+        last_refill: Instant::now(),
+    }));
+}
+
+/// Waits until `bytes` more bytes can be downloaded without going over the
+/// maximum download rate, if there is one
+pub async fn throttle(bytes: usize) {
+    let Some(bucket) = THROTTLE.get() else {
+        return;
+    };
+
+    let wait = bucket.lock().unwrap().take(bytes);
+    if !wait.is_zero() {
+        tokio::time::sleep(wait).await;
+    }
+}
+
+/// Sends the request made by `request`, waiting and retrying when rate limited
+pub async fn send(request: impl Fn() -> RequestBuilder) -> Result<Response> {
+    let mut retries = 0;
+    loop {
+        let response = request().send().await?;
+        if response.status() != StatusCode::TOO_MANY_REQUESTS
+            || retries == MAX_RETRIES
+        {
+            return Ok(response.error_for_status()?);
+        }
+
+        let wait =
+            retry_after(response.headers()).unwrap_or(DEFAULT_RETRY_AFTER);
+        warn!("Rate limited, retrying in {} seconds", wait.as_secs());
+        tokio::time::sleep(wait).await;
+        retries += 1;
+    }
+}
+
+/// Reads how long to wait from the `Retry-After` header, or from Modrinth's
+/// `X-Ratelimit-Reset`
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    [RETRY_AFTER.as_str(), "x-ratelimit-reset"]
+        .into_iter()
+        .filter_map(|name| headers.get(name)?.to_str().ok()?.parse().ok())
+        .next()
+        .map(Duration::from_secs)
+}
+
+/// Downloads `downloadable` like [`download`], trying each of `mirrors` in
+/// order when it fails
+pub async fn download_with_mirrors(
+    downloadable: &Downloadable,
+    output_dir: &Path,
+    mirrors: &[Url],
+) -> Result<()> {
+    let mut result = download(downloadable, output_dir).await;
+    for mirror in mirrors {
+        let Err(e) = &result else {
+            break;
+        };
+        warn!(
+            "Could not download {} ({e}), trying {mirror}",
+            downloadable.filename()
+        );
+        let mut alternate = downloadable.clone();
+        alternate.download_url = mirror.clone();
+        result = download(&alternate, output_dir).await;
+    }
+
+    result
+}
+
+/// Downloads `downloadable` into `output_dir`, through a `.part` file so that
+/// interrupted downloads are never mistaken for complete ones
+pub async fn download(
+    downloadable: &Downloadable,
+    output_dir: &Path,
+) -> Result<()> {
+    let mut response =
+        send(|| CLIENT.get(downloadable.download_url.clone())).await?;
+
+    let out_file_path = output_dir.join(&downloadable.output);
+    let temp_file_path = out_file_path.with_extension("part");
+    let mut temp_file = File::create(&temp_file_path).await?;
+    while let Some(chunk) = response.chunk().await? {
+        throttle(chunk.len()).await;
+        temp_file.write_all(&chunk).await?;
+    }
+    rename(temp_file_path, out_file_path).await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    fn bucket(available: f64, idle: Duration) -> TokenBucket {
+        TokenBucket {
+            rate: 1000.0,
+            available,
+            last_refill: Instant::now() - idle,
+        }
+    }
+
+    #[test]
+    fn bursts_up_to_the_rate_are_free() {
+        let mut bucket = bucket(1000.0, Duration::ZERO);
+
+        assert_eq!(bucket.take(600), Duration::ZERO);
+        assert_eq!(bucket.take(400), Duration::ZERO);
+    }
+
+    #[test]
+    fn debt_is_paid_by_waiting() {
+        let mut bucket = bucket(0.0, Duration::ZERO);
+        let wait = bucket.take(500);
+
+        assert!(wait > Duration::from_millis(490));
+        assert!(wait <= Duration::from_millis(500));
+    }
+
+    #[test]
+    fn refill_is_capped_at_one_second() {
+        let mut bucket = bucket(0.0, Duration::from_secs(10));
+
+        assert_eq!(bucket.take(1000), Duration::ZERO);
+        assert!(bucket.take(1000) > Duration::from_millis(990));
+    }
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.insert(*name, HeaderValue::from_static(value));
+        }
+        headers
+    }
+
+    #[test]
+    fn retry_after_is_read_from_the_headers() {
+        let retry =
+            headers(&[("retry-after", "12"), ("x-ratelimit-reset", "3")]);
+        assert_eq!(retry_after(&retry), Some(Duration::from_secs(12)));
+
+        let modrinth = headers(&[("x-ratelimit-reset", "3")]);
+        assert_eq!(retry_after(&modrinth), Some(Duration::from_secs(3)));
+    }
+
+    #[test]
+    fn unreadable_retry_after_is_ignored() {
+        // http dates are valid but not supported, the default is used
+        let date = headers(&[("retry-after", "Wed, 21 Oct 2015 07:28:00 GMT")]);
+        assert_eq!(retry_after(&date), None);
+
+        let fallback =
+            headers(&[("retry-after", "soon"), ("x-ratelimit-reset", "7")]);
+        assert_eq!(retry_after(&fallback), Some(Duration::from_secs(7)));
+        assert_eq!(retry_after(&HeaderMap::new()), None);
+    }
+}
@@ -0,0 +1,118 @@
+use crate::Result;
+use once_cell::sync::OnceCell;
+use serde::Serialize;
+use std::path::Path;
+use tokio::sync::{mpsc, oneshot};
+use tracing::warn;
+
+/// What is sent to the clients of the --ipc socket, one json object per line
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// A step of the install or launch started
+    Phase { phase: &'static str },
+    /// Files of the modpack were downloaded
+    Progress { done: usize, total: usize },
+    /// uklient is exiting because of an error
+    Error { message: String, exit_code: u8 },
+    /// The game was started
+    Launched { pid: u32 },
+}
+
+enum Message {
+    Line(String),
+    /// Answered once the lines sent before it were written
+    Flush(oneshot::Sender<()>),
+}
+
+/// Where the events go when --ipc is given, written by a background task
+static SENDER: OnceCell<mpsc::UnboundedSender<Message>> = OnceCell::new();
+
+/// Opens a socket at `path` sending the events of this run to every client
+/// that connects to it
+#[cfg(unix)]
+pub fn listen(path: &Path) -> Result<()> {
+    use tokio::io::AsyncWriteExt;
+    use tokio::net::{UnixListener, UnixStream};
+
+    // left behind by a previous run
+    if path.try_exists()? {
+        std::fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+    if SENDER.set(sender).is_err() {
+        return Ok(());
+    }
+
+    tokio::spawn(async move {
+        // every event sent so far, replayed to clients that connect late
+        let mut history = Vec::<String>::new();
+        let mut streams = Vec::<UnixStream>::new();
+        loop {
+            tokio::select! {
+                accepted = listener.accept() => {
+                    let Ok((mut stream, _)) = accepted else { continue };
+                    let mut replayed = Ok(());
+                    for line in &history {
+                        replayed = stream.write_all(line.as_bytes()).await;
+                        if replayed.is_err() {
+                            break;
+                        }
+                    }
+                    if replayed.is_ok() {
+                        streams.push(stream);
+                    }
+                }
+                message = receiver.recv() => match message {
+                    Some(Message::Line(line)) => {
+                        let mut kept = Vec::with_capacity(streams.len());
+                        for mut stream in streams.drain(..) {
+                            match stream.write_all(line.as_bytes()).await {
+                                Ok(()) => kept.push(stream),
+                                Err(e) => tracing::debug!("An ipc client left: {e}"),
+                            }
+                        }
+                        streams = kept;
+                        history.push(line);
+                    }
+                    Some(Message::Flush(done)) => {
+                        let _ = done.send(());
+                    }
+                    None => break,
+                }
+            }
+        }
+    });
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn listen(_path: &Path) -> Result<()> {
+    warn!("--ipc is only supported on Unix, ignoring it");
+    Ok(())
+}
+
+/// Sends `event` to the connected clients, dropping the ones that left
+pub fn emit(event: Event) {
+    let Some(sender) = SENDER.get() else { return };
+    let mut line = match serde_json::to_string(&event) {
+        Ok(line) => line,
+        Err(e) => {
+            warn!("Could not serialize {event:?}: {e}");
+            return;
+        }
+    };
+    line.push('\n');
+    let _ = sender.send(Message::Line(line));
+}
+
+/// Waits for the events emitted so far to be sent, before exiting
+pub async fn flush() {
+    let Some(sender) = SENDER.get() else { return };
+    let (done, written) = oneshot::channel();
+    if sender.send(Message::Flush(done)).is_ok() {
+        let _ = written.await;
+    }
+}
@@ -1,41 +1,119 @@
-use crate::{Result, UklientError, CLIENT, STYLE_BYTE};
+use crate::http;
+use crate::paths::{java_dir, tmp_dir};
+use crate::version::MinecraftVersion;
+use crate::{byte_style, Result, UklientError, CLIENT};
 use flate2::bufread::GzDecoder;
-use indicatif::ProgressBar;
+use indicatif::{HumanBytes, ProgressBar};
 use itertools::Itertools;
 use libium::modpack::extract_zip;
-use libium::HOME;
 use regex::Regex;
 use serde::{Deserialize, Serialize};
 use std::env::consts::{ARCH, OS};
 use std::fs::File;
 use std::ops::Deref;
 use std::path::Path;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::{io::BufReader, path::PathBuf};
 use tar::Archive;
 use theseus::profile::JavaSettings;
 use tokio::fs::{rename, OpenOptions};
 use tokio::io::AsyncWriteExt;
 use tokio::process::Command;
-use tracing::{error, info};
+use tracing::{error, info, warn};
+
+const ONE_SEVENTEEN: MinecraftVersion = MinecraftVersion {
+    minor: 17,
+    patch: 0,
+};
+const ONE_EIGHTEEN: MinecraftVersion = MinecraftVersion {
+    minor: 18,
+    patch: 0,
+};
+const ONE_TWENTY_FIVE: MinecraftVersion = MinecraftVersion {
+    minor: 20,
+    patch: 5,
+};
+/// How often the download progress is logged when the progress bar is hidden
+const REPORT_INTERVAL: Duration = Duration::from_secs(5);
+/// The LTS versions used instead of a Java version that can't be downloaded
+const FALLBACK_JAVA_VERSIONS: &[u8] = &[11, 17, 21];
+
+/// The Java version a Minecraft version runs on
+pub fn required_java_version(game_version: &MinecraftVersion) -> u8 {
+    if *game_version >= ONE_TWENTY_FIVE {
+        21
+    } else if *game_version >= ONE_EIGHTEEN {
+        17
+    } else if *game_version >= ONE_SEVENTEEN {
+        16
+    } else {
+        8
+    }
+}
+
+/// The platforms Java can be downloaded for and the game launched on, as
+/// `std::env::consts::{OS, ARCH}`
+const SUPPORTED_PLATFORMS: &[(&str, &str)] = &[
+    ("linux", "x86_64"),
+    ("linux", "aarch64"),
+    ("windows", "x86_64"),
+    ("windows", "x86"),
+    ("macos", "x86_64"),
+    ("macos", "aarch64"),
+];
+
+/// Adoptium's names for `os` and `arch`, as `std::env::consts::{OS, ARCH}`
+fn adoptium_platform(
+    os: &'static str,
+    arch: &'static str,
+) -> (&'static str, &'static str) {
+    let os = match os {
+        "macos" => "mac",
+        os => os,
+    };
+    let arch = match arch {
+        "x86_64" => "x64",
+        "x86" => "x32",
+        arch => arch,
+    };
+    (os, arch)
+}
+
+/// Fails early with the list of supported platforms instead of letting the
+/// Java download or lookup fail in a confusing way
+pub fn check_platform() -> Result<()> {
+    if SUPPORTED_PLATFORMS.contains(&(OS, ARCH)) {
+        return Ok(());
+    }
+
+    Err(UklientError::UnsupportedPlatform {
+        platform: format!("{OS} {ARCH}"),
+        supported: SUPPORTED_PLATFORMS
+            .iter()
+            .map(|(os, arch)| format!("{os} {arch}"))
+            .join(", "),
+    })
+}
 
 pub async fn get_java_settings(
     java_version: u8,
     force_download: bool,
+    show_progress: bool,
 ) -> JavaSettings {
     let java_name = if cfg!(windows) { "javaw.exe" } else { "java" };
 
     // TODO fork java_locator to look for multiple java versions (cf. prism's implementation of the java locator)
-    let mut java_path =
-        if let Some(java_home_path) = find_local_java(java_version) {
-            info!("Found uklient Java: {java_home_path:?}");
-            Some(java_home_path.join("bin").join(java_name))
-        } else if let Ok(java_home) = java_locator::locate_file(java_name) {
-            info!("Found Java: {java_home:?}");
-            Some(PathBuf::from(java_home).join(java_name))
-        } else {
-            None
-        };
+    let (java_version, mut java_path) = if let Some((version, java_home_path)) =
+        find_local_java(java_version)
+    {
+        info!("Found uklient Java: {java_home_path:?}");
+        (version, Some(java_home_path.join("bin").join(java_name)))
+    } else if let Ok(java_home) = java_locator::locate_file(java_name) {
+        info!("Found Java: {java_home:?}");
+        (java_version, Some(PathBuf::from(java_home).join(java_name)))
+    } else {
+        (java_version, None)
+    };
 
     if force_download
         || java_path.is_none()
@@ -44,9 +122,28 @@ pub async fn get_java_settings(
             .unwrap_or(0)
             != java_version
     {
-        java_path = match download_java(java_version).await {
+        let available = match find_available_java(java_version).await {
+            Ok(v) => v,
+            Err(e) => {
+                warn!("Could not check which Java versions are available: {e}");
+                java_version
+            }
+        };
+        let downloaded = match get_latest_java(available).await {
+            Ok(release) => download_java(&release, show_progress).await,
+            Err(e) => Err(e),
+        };
+        java_path = match downloaded {
             Ok(java_bin_path) => {
                 info!("Found downloaded Java: {java_bin_path:?}");
+                if available != java_version {
+                    let marker = fallback_marker(&java_dir(), java_version);
+                    if let Err(e) =
+                        std::fs::write(marker, available.to_string())
+                    {
+                        warn!("Could not remember the Java fallback: {e}");
+                    }
+                }
                 Some(java_bin_path.join(java_name))
             }
             Err(e) => {
@@ -66,14 +163,83 @@ pub async fn get_java_settings(
     }
 }
 
-async fn download_java(java_version: u8) -> Result<PathBuf> {
-    let java_version = get_latest_java(java_version).await?;
+/// Uses the exact Adoptium build `build`, e.g. `17.0.9+9` or `8u392-b08`,
+/// downloading it if needed
+pub async fn get_exact_java_settings(
+    build: &str,
+    force_download: bool,
+    show_progress: bool,
+) -> Result<JavaSettings> {
+    let java_name = if cfg!(windows) { "javaw.exe" } else { "java" };
+    let release = find_java_release(build).await?;
+    let bin_dir = java_dir().join(&release).join("bin");
+
+    if force_download || !bin_dir.join(java_name).is_file() {
+        download_java(&release, show_progress).await?;
+    } else {
+        info!("Found uklient Java: {release}");
+    }
+    if !bin_dir.join(java_name).is_file() {
+        return Err(UklientError::JavaNotFoundError);
+    }
+
+    Ok(JavaSettings {
+        install: Some(bin_dir.join(java_name)),
+        extra_arguments: None,
+    })
+}
+
+/// Finds the name of the Adoptium release of `build`
+async fn find_java_release(build: &str) -> Result<String> {
+    let wanted = build.trim_start_matches("jdk").trim_start_matches('-');
+    let major = wanted
+        .split(|c: char| !c.is_ascii_digit())
+        .next()
+        .and_then(|major| major.parse::<u8>().ok())
+        .ok_or_else(|| UklientError::JavaBuildUnavailable(build.into()))?;
+
+    // release names are paged, newest first
+    for page in 0.. {
+        let url = format!(
+            "https://api.adoptium.net/v3/info/release_names?project=jdk&release_type=ga&version=[{major},{})&page={page}&page_size=20",
+            major + 1
+        );
+        let response = CLIENT.get(url).send().await?;
+        if !response.status().is_success() {
+            break;
+        }
+        let content: ReleaseNames = response.json().await?;
+        if content.releases.is_empty() {
+            break;
+        }
+
+        let found = content.releases.into_iter().find(|release| {
+            let name =
+                release.trim_start_matches("jdk").trim_start_matches('-');
+            name == wanted
+                || name.starts_with(&format!("{wanted}+"))
+                || name.starts_with(&format!("{wanted}-"))
+        });
+        if let Some(release) = found {
+            return Ok(release);
+        }
+    }
+
+    Err(UklientError::JavaBuildUnavailable(build.into()))
+}
+
+async fn download_java(
+    java_version: &str,
+    show_progress: bool,
+) -> Result<PathBuf> {
+    let (os, arch) = adoptium_platform(OS, ARCH);
     let download_url = format!(
-        "https://api.adoptium.net/v3/binary/version/{java_version}/{OS}/{ARCH}/jdk/hotspot/normal/eclipse"
+        "https://api.adoptium.net/v3/binary/version/{java_version}/{os}/{arch}/jdk/hotspot/normal/eclipse"
     );
 
-    let tmp_dir = HOME.join(".config").join("uklient").join(".tmp");
-    let java_dir = HOME.join(".config").join("uklient");
+    let tmp_dir = tmp_dir();
+    let java_dir = java_dir();
+    tokio::fs::create_dir_all(&tmp_dir).await?;
 
     let mut response = CLIENT.get(download_url).send().await?;
 
@@ -92,13 +258,30 @@ async fn download_java(java_version: u8) -> Result<PathBuf> {
         .await?;
 
     info!("Downloading Java {java_version}");
-    let progress_bar = ProgressBar::new(response.content_length().unwrap_or(0))
-        .with_style(STYLE_BYTE.clone());
+    let length = response.content_length().unwrap_or(0);
+    let progress_bar = if show_progress {
+        ProgressBar::new(length).with_style(byte_style())
+    } else {
+        ProgressBar::hidden()
+    };
     progress_bar.enable_steady_tick(Duration::from_millis(100));
 
+    // the bar is also hidden when stderr isn't a terminal
+    let mut last_report = Instant::now();
     while let Some(chunk) = response.chunk().await? {
+        http::throttle(chunk.len()).await;
         temp_file.write_all(&chunk).await?;
         progress_bar.inc(chunk.len() as u64);
+
+        if progress_bar.is_hidden() && last_report.elapsed() >= REPORT_INTERVAL
+        {
+            info!(
+                "Downloaded {} of {}",
+                HumanBytes(progress_bar.position()),
+                HumanBytes(length)
+            );
+            last_report = Instant::now();
+        }
     }
     rename(&temp_file_path, &out_file_path).await?;
 
@@ -124,6 +307,42 @@ async fn download_java(java_version: u8) -> Result<PathBuf> {
         .ok_or(UklientError::JavaNotFoundError)
 }
 
+/// Returns `java_version` if Adoptium has a build of it for this platform, or
+/// the closest newer version that is available
+async fn find_available_java(java_version: u8) -> Result<u8> {
+    let candidates = std::iter::once(java_version).chain(
+        FALLBACK_JAVA_VERSIONS
+            .iter()
+            .copied()
+            .filter(|&v| v > java_version),
+    );
+
+    for candidate in candidates {
+        if is_java_available(candidate).await? {
+            if candidate != java_version {
+                warn!(
+                    "Java {java_version} is not available for {OS} {ARCH}, using Java {candidate} instead"
+                );
+            }
+            return Ok(candidate);
+        }
+    }
+
+    Err(UklientError::JavaUnavailable(java_version))
+}
+
+async fn is_java_available(java_version: u8) -> Result<bool> {
+    let (os, arch) = adoptium_platform(OS, ARCH);
+    let url = format!(
+        "https://api.adoptium.net/v3/assets/latest/{java_version}/hotspot?architecture={arch}&image_type=jdk&os={os}&vendor=eclipse"
+    );
+
+    let response = CLIENT.get(url).send().await?.error_for_status()?;
+    let assets: Vec<serde_json::Value> = response.json().await?;
+
+    Ok(!assets.is_empty())
+}
+
 async fn get_latest_java(java_version: u8) -> Result<String> {
     let url = format!(
         "https://api.adoptium.net/v3/info/release_names?project=jdk&release_type=ga&version=[{java_version},{})",
@@ -140,27 +359,110 @@ async fn get_latest_java(java_version: u8) -> Result<String> {
         .ok_or(UklientError::JavaNotFoundError)
 }
 
-fn find_local_java(java_version: u8) -> Option<PathBuf> {
-    let uklient_dir = HOME.join(".config").join("uklient");
-    let pattern =
-        Regex::new(format!(r"jdk-{java_version}(?:\.\d+)+(?:\+\d+)?").as_str())
-            .unwrap();
+/// Prints the Java installations that were found and which one would be used
+/// for `java_version`
+pub async fn list_java(java_version: u8) {
+    let java_name = if cfg!(windows) { "javaw.exe" } else { "java" };
 
-    if let Ok(dir) = uklient_dir.read_dir() {
-        let java_name = dir
+    let mut installations = Vec::new();
+    if let Ok(java_home) = java_locator::locate_file(java_name) {
+        installations
+            .push(("system", PathBuf::from(java_home).join(java_name)));
+    }
+    if let Ok(dir) = java_dir().read_dir() {
+        let downloaded = dir
             .filter_map(|res| res.ok())
-            .filter_map(|e| e.path().file_name().map(|s| s.to_os_string()))
-            .filter(|n| pattern.find(&n.to_string_lossy()).is_some())
-            .sorted()
-            .rev()
-            .next();
+            .map(|e| e.path().join("bin").join(java_name))
+            .filter(|p| p.is_file())
+            .sorted();
+        installations.extend(downloaded.map(|p| ("downloaded", p)));
+    }
 
-        java_name.map(|name| uklient_dir.join(name))
-    } else {
-        None
+    if installations.is_empty() {
+        println!("No Java installation found");
+    }
+    let mut selected = find_local_java(java_version)
+        .map(|(_, home)| home.join("bin").join(java_name));
+    for (source, path) in &installations {
+        let version = get_java_version(path).await.ok();
+        let arch = get_java_arch(path).await.unwrap_or_else(|| "?".into());
+        println!(
+            "{} ({source}) - Java {} - {arch}",
+            path.display(),
+            version.map_or_else(|| "?".into(), |v| v.to_string())
+        );
+        if selected.is_none() && version == Some(java_version) {
+            selected = Some(path.clone());
+        }
+    }
+
+    match selected {
+        Some(path) => {
+            println!("{} would be used for Java {java_version}", path.display())
+        }
+        None => println!("Java {java_version} would be downloaded"),
     }
 }
 
+async fn get_java_arch(exec_path: &Path) -> Option<String> {
+    let regex = Regex::new(r"os\.arch = (\S+)").unwrap();
+    let output = Command::new(exec_path.as_os_str())
+        .args(["-XshowSettings:properties", "-version"])
+        .output()
+        .await
+        .ok()?;
+
+    let text = String::from_utf8_lossy(&output.stderr);
+    regex
+        .captures(&text)
+        .and_then(|c| c.get(1))
+        .map(|arch| arch.as_str().to_string())
+}
+
+/// Finds the Java downloaded by uklient for `java_version`, or the newer one
+/// that was downloaded in its place, returning its version and home
+fn find_local_java(java_version: u8) -> Option<(u8, PathBuf)> {
+    find_local_java_in(&java_dir(), java_version)
+}
+
+fn find_local_java_in(dir: &Path, java_version: u8) -> Option<(u8, PathBuf)> {
+    if let Some(home) = find_jdk(dir, java_version) {
+        return Some((java_version, home));
+    }
+
+    let fallback = std::fs::read_to_string(fallback_marker(dir, java_version))
+        .ok()?
+        .trim()
+        .parse()
+        .ok()?;
+    find_jdk(dir, fallback).map(|home| (fallback, home))
+}
+
+/// The newest JDK of `java_version` in `dir`, e.g. `jdk-17.0.9+9` or
+/// `jdk8u392-b08`
+fn find_jdk(dir: &Path, java_version: u8) -> Option<PathBuf> {
+    let pattern =
+        Regex::new(format!(r"^jdk-?{java_version}(?:u\d+|[.+])").as_str())
+            .unwrap();
+
+    dir.read_dir()
+        .ok()?
+        .filter_map(|res| res.ok())
+        .filter(|e| e.path().is_dir())
+        .map(|e| e.file_name().to_string_lossy().into_owned())
+        .filter(|n| pattern.is_match(n))
+        .sorted()
+        .rev()
+        .next()
+        .map(|name| dir.join(name))
+}
+
+/// Remembers which Java was downloaded because `java_version` isn't available
+/// for this platform
+fn fallback_marker(dir: &Path, java_version: u8) -> PathBuf {
+    dir.join(format!("java-{java_version}-fallback"))
+}
+
 async fn get_java_version(exec_path: &Path) -> Result<u8> {
     let regex = Regex::new(r#"version "(\d+\.\d+\.\d+)(?:_\d+)?""#).unwrap();
 
@@ -196,3 +498,42 @@ async fn get_java_version(exec_path: &Path) -> Result<u8> {
 struct ReleaseNames {
     releases: Vec<String>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn platforms_use_adoptium_names() {
+        assert_eq!(adoptium_platform("linux", "x86_64"), ("linux", "x64"));
+        assert_eq!(adoptium_platform("macos", "aarch64"), ("mac", "aarch64"));
+        assert_eq!(adoptium_platform("windows", "x86"), ("windows", "x32"));
+    }
+
+    #[test]
+    fn downloaded_fallback_is_found_again() {
+        let dir = std::env::temp_dir()
+            .join(format!("uklient-java-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("jdk-11.0.21+9")).unwrap();
+        std::fs::create_dir_all(dir.join("jdk-17.0.9+9")).unwrap();
+        assert_eq!(find_local_java_in(&dir, 8), None);
+
+        // the first run downloaded Java 11 instead of 8
+        std::fs::write(fallback_marker(&dir, 8), "11").unwrap();
+        assert_eq!(
+            find_local_java_in(&dir, 8),
+            Some((11, dir.join("jdk-11.0.21+9")))
+        );
+
+        std::fs::create_dir_all(dir.join("jdk8u392-b08")).unwrap();
+        assert_eq!(
+            find_local_java_in(&dir, 8),
+            Some((8, dir.join("jdk8u392-b08")))
+        );
+        assert_eq!(
+            find_local_java_in(&dir, 17),
+            Some((17, dir.join("jdk-17.0.9+9")))
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
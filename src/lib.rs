@@ -0,0 +1,661 @@
+pub mod auth;
+pub mod config;
+pub mod gpu;
+pub mod history;
+pub mod http;
+pub mod ipc;
+pub mod java;
+pub mod loader;
+pub mod manifest;
+pub mod memory;
+pub mod modpack;
+pub mod modrinth;
+pub mod paths;
+pub mod priority;
+pub mod profiles;
+pub mod state;
+pub mod telemetry;
+pub mod timings;
+pub mod update;
+pub mod version;
+
+use crate::auth::{
+    get_credentials, get_device_code, refresh_credentials, PendingLogin,
+};
+use chrono::Utc;
+use indicatif::ProgressStyle;
+use once_cell::sync::{Lazy, OnceCell};
+use reqwest::Client;
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use theseus::auth::Credentials;
+use theseus::data::{MemorySettings, WindowSize};
+use theseus::profile::{
+    self, JavaSettings, ModLoader, Profile, ProfileMetadata,
+};
+use thiserror::Error;
+use tokio::process::Child;
+use tokio::sync::{oneshot, Mutex};
+use tracing::{info, warn};
+
+pub type Result<T> = std::result::Result<T, UklientError>;
+
+pub static STYLE_BYTE: Lazy<ProgressStyle> = Lazy::new(|| {
+    ProgressStyle::default_bar()
+        .template("{bytes_per_sec} [{bar:30}] {bytes}/{total_bytes}")
+        .expect("Progess bar template parse failure")
+        .progress_chars("#>-")
+});
+pub static STYLE_COUNT: Lazy<ProgressStyle> = Lazy::new(|| {
+    ProgressStyle::default_bar()
+        .template("[{bar:30}] {pos}/{len}")
+        .expect("Progess bar template parse failure")
+        .progress_chars("#>-")
+});
+/// The style given with --progress-template, used instead of `STYLE_BYTE`
+static CUSTOM_STYLE: OnceCell<ProgressStyle> = OnceCell::new();
+
+/// Uses `template` for the download progress bars, for the rest of the run.
+/// An invalid template is only warned about
+pub fn set_progress_template(template: &str) {
+    match ProgressStyle::default_bar().template(template) {
+        Ok(style) => {
+            let _ = CUSTOM_STYLE.set(style.progress_chars("#>-"));
+        }
+        Err(e) => warn!("Invalid progress bar template, ignoring it: {e}"),
+    }
+}
+
+/// The style of the download progress bars
+pub fn byte_style() -> ProgressStyle {
+    CUSTOM_STYLE.get().unwrap_or(&STYLE_BYTE).clone()
+}
+
+/// Idle connections kept per host, installs make many requests to a handful
+/// of hosts (the Modrinth API and CDN, Adoptium, maven repositories)
+const POOL_MAX_IDLE_PER_HOST: usize = 32;
+const POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+const TCP_KEEPALIVE: Duration = Duration::from_secs(60);
+pub static CLIENT: Lazy<Client> = Lazy::new(|| {
+    Client::builder()
+        .pool_max_idle_per_host(POOL_MAX_IDLE_PER_HOST)
+        .pool_idle_timeout(POOL_IDLE_TIMEOUT)
+        .tcp_keepalive(TCP_KEEPALIVE)
+        .tcp_nodelay(true)
+        // large files download faster when http/2 can grow its window
+        .http2_adaptive_window(true)
+        .build()
+        .expect("Could not build the http client")
+});
+pub const USER_AGENT: &str = concat!("uklient-rs/", env!("CARGO_PKG_VERSION"));
+/// The account connected during this run, reused until it expires
+static CREDENTIALS: Lazy<Mutex<Option<Credentials>>> =
+    Lazy::new(|| Mutex::new(None));
+
+pub const DEFAULT_RESOLUTION: WindowSize = WindowSize(1280, 720);
+
+/// Where the profile of a modpack is installed
+pub fn profile_dir(metadata: &ProfileMetadata) -> PathBuf {
+    // grr theseus
+    paths::data_dir().join(metadata.name.replace(' ', "_"))
+}
+
+/// Builds the theseus profile of a modpack, `mods_dir` being where the mods
+/// are installed if it isn't the profile's `mods` folder
+pub fn new_profile(
+    path: PathBuf,
+    metadata: ProfileMetadata,
+    mut java: JavaSettings,
+    memory: MemorySettings,
+    resolution: WindowSize,
+    mods_dir: Option<&Path>,
+) -> Profile {
+    if let Some(mods_dir) = mods_dir {
+        // the loaders only look in the profile's mods folder by default
+        let property = match metadata.loader {
+            ModLoader::Quilt => Some("loader.addMods"),
+            ModLoader::Forge => None,
+            _ => Some("fabric.addMods"),
+        };
+        match property {
+            Some(property) => {
+                java.extra_arguments =
+                    Some(vec![format!("-D{property}={}", mods_dir.display())])
+            }
+            None => warn!(
+                "Forge only loads the profile's mods folder, the mods in {} won't be loaded",
+                mods_dir.display()
+            ),
+        }
+    }
+
+    Profile {
+        path,
+        metadata,
+        java: Some(java),
+        memory: Some(memory),
+        resolution: Some(resolution),
+        hooks: None,
+    }
+}
+
+/// Applies what an install decides to an existing profile, keeping the rest of
+/// its settings (name, hooks, extra JVM arguments) as the user left them
+pub fn merge_profile(existing: &mut Profile, new: Profile) {
+    let new_java = new.java.unwrap_or_default();
+    let java = existing.java.get_or_insert_with(JavaSettings::default);
+    java.install = new_java.install;
+
+    // the mods folder properties come from uklient, the rest is the user's
+    let mut arguments = java.extra_arguments.take().unwrap_or_default();
+    arguments.retain(|a| {
+        !a.starts_with("-Dfabric.addMods=")
+            && !a.starts_with("-Dloader.addMods=")
+    });
+    arguments.extend(new_java.extra_arguments.unwrap_or_default());
+    java.extra_arguments = (!arguments.is_empty()).then_some(arguments);
+
+    // the name can be changed with `profiles rename`
+    let name = std::mem::take(&mut existing.metadata.name);
+    existing.metadata = new.metadata;
+    existing.metadata.name = name;
+    existing.memory = new.memory;
+    existing.resolution = new.resolution;
+}
+
+/// Sets whether the game starts in fullscreen in the `options.txt` of a
+/// profile, theseus having no setting for it
+pub fn set_fullscreen(profile_dir: &Path, fullscreen: bool) -> Result<()> {
+    let path = profile_dir.join("options.txt");
+    let options = match std::fs::read_to_string(&path) {
+        Ok(options) => options,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut lines = options
+        .lines()
+        .filter(|line| !line.starts_with("fullscreen:"))
+        .map(String::from)
+        .collect::<Vec<_>>();
+    lines.push(format!("fullscreen:{fullscreen}"));
+    std::fs::write(path, lines.join("\n") + "\n")?;
+    Ok(())
+}
+
+/// Checks that theseus registered the profile and that the launcher profile of
+/// its loader can be read, so that a broken install fails now instead of at
+/// launch
+pub async fn verify_profile(path: &Path) -> Result<()> {
+    let profile = profile::get(path)
+        .await?
+        .ok_or_else(|| UklientError::ProfileNotRegistered(path.into()))?;
+    if profile.metadata.loader == ModLoader::Vanilla {
+        return Ok(());
+    }
+
+    match &profile.metadata.loader_version {
+        Some(version) => loader::check_loader_profile(version).await,
+        None => Err(UklientError::InvalidLoaderProfile {
+            id: profile.metadata.loader.to_string(),
+            message: "the profile has no loader version".into(),
+        }),
+    }
+}
+
+/// Launches a profile, adding what was being launched to theseus' errors
+pub async fn run_profile(
+    path: &Path,
+    credentials: &Credentials,
+) -> Result<Child> {
+    match profile::run(path, credentials).await {
+        Ok(child) => Ok(child),
+        Err(source) => {
+            let (game_version, java) = match profile::get(path).await {
+                Ok(Some(profile)) => (
+                    format!(
+                        "{} ({})",
+                        profile.metadata.game_version, profile.metadata.loader
+                    ),
+                    profile.java.and_then(|java| java.install),
+                ),
+                _ => ("unknown".into(), None),
+            };
+
+            Err(UklientError::LaunchError {
+                profile: path.to_path_buf(),
+                game_version,
+                java,
+                source,
+            })
+        }
+    }
+}
+
+/// How the Microsoft account gets connected
+#[derive(Debug, Clone, Default)]
+pub struct LoginOptions {
+    /// Whether to log in again even if an account is saved
+    pub force_relogin: bool,
+    /// The saved account to use instead of the last one, by username
+    pub account: Option<String>,
+    /// Whether failing to use a saved account is an error instead of asking
+    /// the user to log in
+    pub non_interactive: bool,
+}
+
+/// Returns the saved Microsoft account, logging in with a device code if there
+/// is none or if `force_relogin` is set
+pub async fn connect_account(options: &LoginOptions) -> Result<Credentials> {
+    // held for the whole login so concurrent calls don't refresh twice
+    let mut cached = CREDENTIALS.lock().await;
+    if !options.force_relogin {
+        if let Some(creds) = cached.as_ref().filter(|c| {
+            c.expires > Utc::now()
+                && options.account.as_ref().map_or(true, |a| *a == c.username)
+        }) {
+            return Ok(creds.clone());
+        }
+    }
+
+    let creds = login(options).await?;
+    *cached = Some(creds.clone());
+    Ok(creds)
+}
+
+/// Whether the saved credentials can be used after failing to refresh them.
+/// The token still works until it expires, so only a refresh that was
+/// rejected (rather than one that couldn't reach the server) needs a new login
+fn can_use_saved(error: &UklientError, creds: &Credentials) -> bool {
+    let network_error = match error {
+        UklientError::ReqwestError(e) => {
+            e.status().is_none()
+                && (e.is_connect() || e.is_timeout() || e.is_request())
+        }
+        _ => false,
+    };
+
+    network_error && creds.expires > Utc::now()
+}
+
+async fn login(options: &LoginOptions) -> Result<Credentials> {
+    let account = options.account.as_deref();
+    // an explicitly chosen account never falls back to another one
+    let strict = account.is_some() || options.non_interactive;
+
+    if !options.force_relogin {
+        match (read_credentials(account), account) {
+            (Ok(Some(creds)), _) => {
+                match refresh_credentials(creds.clone()).await {
+                    Ok(creds) => return Ok(creds),
+                    Err(e) if can_use_saved(&e, &creds) => {
+                        warn!("Could not refresh the account, using the saved token: {e}");
+                        return Ok(creds);
+                    }
+                    Err(e) if strict => {
+                        return Err(UklientError::LoginError(format!(
+                            "could not refresh the saved account: {e}"
+                        )))
+                    }
+                    Err(_) => {}
+                }
+            }
+            (Ok(None), Some(account)) => {
+                return Err(UklientError::LoginError(format!(
+                    "no saved account named {account}"
+                )))
+            }
+            (Err(e), _) if strict => return Err(e),
+            _ => {}
+        }
+    }
+    if options.non_interactive {
+        return Err(UklientError::NeedsInteraction("logging in"));
+    }
+
+    let pending = match PendingLogin::load()? {
+        Some(pending) => {
+            info!("Resuming the login started earlier");
+            pending
+        }
+        None => {
+            let scopes = vec!["XboxLive.signin", "offline_access"];
+            let pending = PendingLogin::new(get_device_code(scopes).await?);
+            pending.save()?;
+            pending
+        }
+    };
+    warn!(
+        "No account was found, please go to {} and enter the code {}",
+        pending.code.verification_uri, pending.code.user_code
+    );
+
+    let creds = get_credentials(pending.code.device_code).await;
+    // the code can't be used again once it has been accepted or refused,
+    // network errors keep it for the next try
+    if matches!(creds, Ok(_) | Err(UklientError::LoginError(_))) {
+        PendingLogin::clear()?;
+    }
+    let creds = creds?;
+    // saved as the default account, and under its name for --account
+    for path in [
+        paths::credentials_path(),
+        paths::account_path(&creds.username),
+    ] {
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let file = File::create(path)?;
+        serde_json::to_writer(BufWriter::new(file), &creds)?;
+    }
+
+    Ok(creds)
+}
+
+/// Reads the saved credentials of `account` (a username or UUID), or of the
+/// default account, without refreshing them
+pub fn read_credentials(account: Option<&str>) -> Result<Option<Credentials>> {
+    let credentials_path = match account {
+        Some(account) => paths::account_path(&account_name(account)),
+        None => paths::credentials_path(),
+    };
+    if !credentials_path.try_exists()? {
+        return Ok(None);
+    }
+
+    let file = File::open(&credentials_path)?;
+    match serde_json::from_reader(BufReader::new(file)) {
+        Ok(creds) => Ok(Some(creds)),
+        // a corrupted file is replaced by the next login instead of blocking
+        // every launch
+        Err(e) => {
+            let backup = credentials_path.with_extension("json.bak");
+            warn!(
+                "{credentials_path:?} is corrupted, moving it to {backup:?}: {e}"
+            );
+            std::fs::rename(&credentials_path, backup)?;
+            Ok(None)
+        }
+    }
+}
+
+/// Credentials for playing without a Microsoft account, with the UUID the game
+/// gives to offline players so that their worlds and settings are kept
+pub fn offline_credentials(username: &str) -> Credentials {
+    let hash = md5::compute(format!("OfflinePlayer:{username}"));
+    Credentials {
+        id: uuid::Builder::from_md5_bytes(hash.0).into_uuid(),
+        username: username.into(),
+        access_token: "0".into(),
+        refresh_token: String::new(),
+        expires: Utc::now() + chrono::Duration::days(1),
+        _ctor_scope: std::marker::PhantomData,
+    }
+}
+
+/// The usernames of the accounts that can be used with `--account`
+pub fn saved_accounts() -> Vec<String> {
+    let Ok(dir) = paths::accounts_dir().read_dir() else {
+        return Vec::new();
+    };
+    let mut accounts = dir
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            name.strip_suffix(".json").map(String::from)
+        })
+        .collect::<Vec<_>>();
+    accounts.sort();
+    accounts
+}
+
+/// The username of the saved account named `account` or with `account` as
+/// its UUID, `account` itself if there is none
+fn account_name(account: &str) -> String {
+    if paths::account_path(account).is_file() {
+        return account.into();
+    }
+
+    let has_id = |name: &String| {
+        File::open(paths::account_path(name))
+            .ok()
+            .and_then(|file| {
+                serde_json::from_reader::<_, Credentials>(BufReader::new(file))
+                    .ok()
+            })
+            .map_or(false, |creds| {
+                creds.id.to_string() == account
+                    || creds.id.simple().to_string() == account
+            })
+    };
+    saved_accounts()
+        .into_iter()
+        .find(has_id)
+        .unwrap_or_else(|| account.into())
+}
+
+/// Makes the saved `account` the one used when no --account is given
+pub fn use_account(account: &str) -> Result<Credentials> {
+    let creds = read_credentials(Some(account))?.ok_or_else(|| {
+        UklientError::LoginError(format!("no saved account named {account}"))
+    })?;
+    std::fs::copy(
+        paths::account_path(&creds.username),
+        paths::credentials_path(),
+    )?;
+    Ok(creds)
+}
+
+/// Deletes the saved `account`, and the default credentials if they are the
+/// ones of that account
+pub fn remove_account(account: &str) -> Result<Credentials> {
+    let creds = read_credentials(Some(account))?.ok_or_else(|| {
+        UklientError::LoginError(format!("no saved account named {account}"))
+    })?;
+    std::fs::remove_file(paths::account_path(&creds.username))?;
+    if read_credentials(None)?.map_or(false, |c| c.id == creds.id) {
+        std::fs::remove_file(paths::credentials_path())?;
+    }
+    Ok(creds)
+}
+
+/// Moves the credentials that older versions saved in the working directory
+/// to where they are stored now
+pub fn migrate_credentials() {
+    let moves = [
+        (PathBuf::from("credentials.json"), paths::credentials_path()),
+        (PathBuf::from("accounts"), paths::accounts_dir()),
+    ];
+    for (old, new) in moves {
+        if !old.exists() || new.exists() {
+            continue;
+        }
+        let moved = new
+            .parent()
+            .map_or(Ok(()), std::fs::create_dir_all)
+            .and_then(|_| std::fs::rename(&old, &new));
+        match moved {
+            Ok(()) => info!("Moved {old:?} to {new:?}"),
+            Err(e) => warn!("Could not move {old:?} to {new:?}: {e}"),
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+#[allow(clippy::enum_variant_names)]
+pub enum UklientError {
+    #[error("Java could not be located: {0}")]
+    JavaLocateError(#[from] java_locator::errors::JavaLocatorError),
+    #[error("tokio recv error: {0}")]
+    RecvError(#[from] oneshot::error::RecvError),
+    #[error("io error: {0}")]
+    IoError(#[from] std::io::Error),
+    #[error("fs_extra error: {0}")]
+    FsExtraError(#[from] fs_extra::error::Error),
+    #[error("tokio join error: {0}")]
+    JoinError(#[from] tokio::task::JoinError),
+    #[error("theseus error: {0}")]
+    TheseusError(#[from] theseus::Error),
+    #[error("daedalus error: {0}")]
+    DaedalusError(#[from] daedalus::Error),
+    #[error("json error: {0}")]
+    JsonError(#[from] serde_json::Error),
+    #[error("libium error: {0}")]
+    LibiumError(#[from] libium::upgrade::Error),
+    #[error("libium modpack error: {0}")]
+    LibiumModpackError(#[from] libium::upgrade::modpack_downloadable::Error),
+    #[error("zip error")]
+    ZipError,
+    #[error("invalid modpack index, at {path}: {message}")]
+    InvalidIndex { path: String, message: String },
+    #[error("{0:?} has no download url")]
+    MissingDownload(PathBuf),
+    #[error("{0} version not found")]
+    MetaError(&'static str),
+    #[error("unknown type: {0:?}")]
+    UnknownTypeError(OsString),
+    #[error("tokio acquire error: {0}")]
+    AcquireError(#[from] tokio::sync::AcquireError),
+    #[error("reqwest error: {0}")]
+    ReqwestError(#[from] reqwest::Error),
+    #[error("java not found")]
+    JavaNotFoundError,
+    #[error("{platform} is not supported, uklient runs on {supported}")]
+    UnsupportedPlatform { platform: String, supported: String },
+    #[error("Java {0} can't be downloaded for this platform")]
+    JavaUnavailable(u8),
+    #[error("Java build {0} is not available from Adoptium")]
+    JavaBuildUnavailable(String),
+    #[error("minecraft version error: {0}")]
+    VersionError(#[from] crate::version::VersionError),
+    #[error("url parse error: {0}")]
+    UrlParseError(#[from] url::ParseError),
+    #[error("login error: {0}")]
+    LoginError(String),
+    #[error("invalid modpack id {0:?}")]
+    InvalidModpackId(String),
+    #[error("modpack {0} not found")]
+    ModpackNotFound(String),
+    #[error("no installed profile found for modpack {0}")]
+    ManifestNotFound(String),
+    #[error("smoke test failed: {0}")]
+    SmokeTestFailed(&'static str),
+    #[error("the game exited as soon as it was launched ({0})")]
+    EarlyExit(String),
+    #[error(
+        "the game was killed after not reaching the main menu in {0} seconds"
+    )]
+    LaunchTimeout(u64),
+    #[error("could not launch {profile:?} (Minecraft {game_version}, Java {java:?}): {source}")]
+    LaunchError {
+        profile: PathBuf,
+        game_version: String,
+        java: Option<PathBuf>,
+        source: theseus::Error,
+    },
+    #[error("the game can't be given {requested} MB of memory, this machine only has {total} MB")]
+    NotEnoughMemory { requested: u32, total: u64 },
+    #[error("{0} servers could not be reached")]
+    SelfTestFailed(usize),
+    #[error("{failed} of {total} modpacks could not be installed")]
+    BatchFailed { failed: usize, total: usize },
+    #[error("no profile named {0}, see `uklient profiles list`")]
+    UnknownProfile(String),
+    #[error("a profile named {0} already exists")]
+    ProfileExists(String),
+    #[error("{0:?} was not registered as a profile")]
+    ProfileNotRegistered(PathBuf),
+    #[error("the launcher profile of loader {id} is invalid: {message}")]
+    InvalidLoaderProfile { id: String, message: String },
+    #[error("{0} mods don't support the loader, see the warnings above")]
+    IncompatibleMods(usize),
+    #[error(
+        "{0} mods failed to download, run the install again to retry them"
+    )]
+    DownloadsFailed(usize),
+    #[error("{0} is locked, use --unlock to change it")]
+    ProfileLocked(String),
+    #[error("checksum mismatch for {0}")]
+    ChecksumMismatch(String),
+    #[error("invalid sha1 {hash:?} for {file}")]
+    InvalidHash { file: String, hash: String },
+    #[error("config file {0:?} not found")]
+    ConfigNotFound(PathBuf),
+    #[error("config error: {0}")]
+    ConfigError(#[from] toml::de::Error),
+    #[error("{0} needs user interaction, but --non-interactive was given")]
+    NeedsInteraction(&'static str),
+    #[error("interrupted by the user")]
+    Interrupted,
+    #[error("{0} is not available offline, launch once with a connection")]
+    NotCached(&'static str),
+}
+
+impl UklientError {
+    /// The exit code for this kind of error, these are stable so that scripts
+    /// can rely on them (see the README)
+    pub fn exit_code(&self) -> u8 {
+        use UklientError::*;
+
+        match self {
+            ReqwestError(_) | DaedalusError(_) | LibiumError(_)
+            | DownloadsFailed(_) | SelfTestFailed(_) => 2,
+            LoginError(_) => 3,
+            MetaError(_)
+            | VersionError(_)
+            | ModpackNotFound(_)
+            | InvalidModpackId(_)
+            | ManifestNotFound(_)
+            | UnknownProfile(_)
+            | InvalidLoaderProfile { .. } => 4,
+            JavaLocateError(_)
+            | JavaNotFoundError
+            | JavaUnavailable(_)
+            | JavaBuildUnavailable(_)
+            | UnsupportedPlatform { .. } => 5,
+            ZipError
+            | InvalidIndex { .. }
+            | MissingDownload(_)
+            | InvalidHash { .. }
+            | LibiumModpackError(_) => 6,
+            SmokeTestFailed(_) | EarlyExit(_) | LaunchTimeout(_) => 7,
+            Interrupted => 130,
+            _ => 1,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile(name: &str, game_version: &str) -> Profile {
+        new_profile(
+            PathBuf::from("/profiles/pack"),
+            ProfileMetadata {
+                name: name.into(),
+                loader: ModLoader::Fabric,
+                loader_version: None,
+                game_version: game_version.into(),
+                format_version: 1,
+                icon: None,
+            },
+            JavaSettings::default(),
+            MemorySettings::default(),
+            WindowSize(854, 480),
+            None,
+        )
+    }
+
+    #[test]
+    fn merging_keeps_the_renamed_profile_name() {
+        let mut existing = profile("my pack", "1.19.2");
+        merge_profile(&mut existing, profile("pack", "1.19.3"));
+
+        assert_eq!(existing.metadata.name, "my pack");
+        assert_eq!(existing.metadata.game_version, "1.19.3");
+    }
+}
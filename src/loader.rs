@@ -0,0 +1,533 @@
+use crate::Result;
+use crate::UklientError::{ChecksumMismatch, InvalidLoaderProfile, MetaError};
+use clap::ValueEnum;
+use daedalus::modded::LoaderVersion;
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+pub const FABRIC_META_URL: &str = "https://meta.fabricmc.net/v2";
+pub const QUILT_META_URL: &str = "https://meta.quiltmc.org/v3";
+pub const LEGACY_FABRIC_META_URL: &str = "https://meta.legacyfabric.net/v2";
+/// Modrinth's mirrors of the Forge and NeoForge versions, as daedalus
+/// manifests
+pub const FORGE_MANIFEST_URL: &str =
+    "https://meta.modrinth.com/forge/v0/manifest.json";
+pub const NEOFORGE_MANIFEST_URL: &str =
+    "https://meta.modrinth.com/neo/v0/manifest.json";
+
+/// The mod loaders uklient can install
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Deserialize,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum Loader {
+    #[default]
+    Fabric,
+    Quilt,
+    /// Fabric for Minecraft 1.13 and older
+    LegacyFabric,
+    Forge,
+    #[value(name = "neoforge")]
+    #[serde(rename = "neoforge")]
+    NeoForge,
+}
+
+impl Loader {
+    /// Parses the name Modrinth gives to a loader
+    pub fn from_modrinth(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "fabric" => Some(Self::Fabric),
+            "quilt" => Some(Self::Quilt),
+            "legacy-fabric" => Some(Self::LegacyFabric),
+            "forge" => Some(Self::Forge),
+            "neoforge" => Some(Self::NeoForge),
+            _ => None,
+        }
+    }
+
+    /// The name Modrinth gives to this loader, which is also the one stored in
+    /// the manifests
+    pub fn modrinth_name(self) -> &'static str {
+        match self {
+            Self::Fabric => "fabric",
+            Self::Quilt => "quilt",
+            Self::LegacyFabric => "legacy-fabric",
+            Self::Forge => "forge",
+            Self::NeoForge => "neoforge",
+        }
+    }
+
+    /// Whether mods made for the Modrinth loader `name` run on this loader
+    pub fn runs(self, name: &str) -> bool {
+        match (self, Self::from_modrinth(name)) {
+            (_, None) => false,
+            (Self::Forge | Self::NeoForge, Some(loader)) => loader == self,
+            (_, Some(loader)) => loader == self || loader == Self::Fabric,
+        }
+    }
+}
+
+/// The latest fabric loader for `mc_version`, from the meta server at
+/// `meta_url` (usually `FABRIC_META_URL`)
+pub async fn get_latest_fabric(
+    meta_url: &str,
+    mc_version: &str,
+    prefer_stable: bool,
+) -> Result<LoaderVersion> {
+    get_latest_loader(meta_url, mc_version, "fabric", prefer_stable).await
+}
+
+/// The latest quilt loader for `mc_version`, from the meta server at
+/// `meta_url` (usually `QUILT_META_URL`)
+pub async fn get_latest_quilt(
+    meta_url: &str,
+    mc_version: &str,
+    prefer_stable: bool,
+) -> Result<LoaderVersion> {
+    get_latest_loader(meta_url, mc_version, "quilt", prefer_stable).await
+}
+
+/// The latest legacy fabric loader for `mc_version`, from the meta server at
+/// `meta_url` (usually `LEGACY_FABRIC_META_URL`)
+pub async fn get_latest_legacy_fabric(
+    meta_url: &str,
+    mc_version: &str,
+    prefer_stable: bool,
+) -> Result<LoaderVersion> {
+    get_latest_loader(meta_url, mc_version, "legacy fabric", prefer_stable)
+        .await
+}
+
+pub async fn get_latest_forge(
+    mc_version: &str,
+    prefer_stable: bool,
+) -> Result<LoaderVersion> {
+    let versions = list_modded_loaders(FORGE_MANIFEST_URL, mc_version).await?;
+    pick_loader(versions, "forge", prefer_stable)
+}
+
+pub async fn get_latest_neoforge(
+    mc_version: &str,
+    prefer_stable: bool,
+) -> Result<LoaderVersion> {
+    let versions =
+        list_modded_loaders(NEOFORGE_MANIFEST_URL, mc_version).await?;
+    pick_loader(versions, "neoforge", prefer_stable)
+}
+
+/// Lists the versions of `loader` available for `mc_version`, newest first
+pub async fn list_loader_versions(
+    loader: Loader,
+    mc_version: &str,
+) -> Result<Vec<LoaderVersion>> {
+    let meta_url = match loader {
+        Loader::Fabric => FABRIC_META_URL,
+        Loader::Quilt => QUILT_META_URL,
+        Loader::LegacyFabric => LEGACY_FABRIC_META_URL,
+        Loader::Forge => {
+            return list_modded_loaders(FORGE_MANIFEST_URL, mc_version).await
+        }
+        Loader::NeoForge => {
+            return list_modded_loaders(NEOFORGE_MANIFEST_URL, mc_version).await
+        }
+    };
+    list_loaders(meta_url, mc_version).await
+}
+
+/// Fetches the latest loader for `mc_version` from a fabric-like meta server.
+/// With `prefer_stable`, prereleases are only used if there is no stable one
+async fn get_latest_loader(
+    meta_url: &str,
+    mc_version: &str,
+    name: &'static str,
+    prefer_stable: bool,
+) -> Result<LoaderVersion> {
+    let versions = list_loaders(meta_url, mc_version).await?;
+    pick_loader(versions, name, prefer_stable)
+}
+
+/// The newest loader of `versions`, or the newest stable one with
+/// `prefer_stable` if there is one
+fn pick_loader(
+    versions: Vec<LoaderVersion>,
+    name: &'static str,
+    prefer_stable: bool,
+) -> Result<LoaderVersion> {
+    let stable = versions.iter().position(|v| v.stable);
+    let index = match stable {
+        Some(index) if prefer_stable => index,
+        _ => 0,
+    };
+
+    versions.into_iter().nth(index).ok_or(MetaError(name))
+}
+
+/// Fetches all the loaders for `mc_version` from a fabric-like meta server
+async fn list_loaders(
+    meta_url: &str,
+    mc_version: &str,
+) -> Result<Vec<LoaderVersion>> {
+    let downloaded = daedalus::download_file(
+        format!("{meta_url}/versions/loader/{mc_version}").as_str(),
+        None,
+    )
+    .await?;
+
+    let versions: Vec<LoaderVersionElement> =
+        serde_json::from_slice(&downloaded)?;
+    Ok(versions
+        .into_iter()
+        .map(|element| LoaderVersion {
+            url: format!(
+                "{meta_url}/versions/loader/{mc_version}/{}/profile/json",
+                element.loader.version
+            ),
+            id: element.loader.version,
+            stable: element.loader.stable,
+        })
+        .collect())
+}
+
+/// Fetches all the loaders for `mc_version` from a daedalus modded manifest
+async fn list_modded_loaders(
+    manifest_url: &str,
+    mc_version: &str,
+) -> Result<Vec<LoaderVersion>> {
+    let manifest = daedalus::modded::fetch_manifest(manifest_url).await?;
+    Ok(manifest
+        .game_versions
+        .into_iter()
+        .find(|version| version.id == mc_version)
+        .map(|version| version.loaders)
+        .unwrap_or_default())
+}
+
+/// Checks that the launcher profile of a loader can be downloaded and read
+pub async fn check_loader_profile(loader: &LoaderVersion) -> Result<()> {
+    let invalid = |message: String| InvalidLoaderProfile {
+        id: loader.id.clone(),
+        message,
+    };
+    let downloaded = daedalus::download_file(&loader.url, None)
+        .await
+        .map_err(|e| invalid(e.to_string()))?;
+    let profile: LoaderProfile = serde_json::from_slice(&downloaded)
+        .map_err(|e| invalid(e.to_string()))?;
+
+    if profile.main_class.is_empty() {
+        return Err(invalid("it has no main class".into()));
+    }
+    Ok(())
+}
+
+/// Checks the libraries of a loader against the sha1 checksums published next
+/// to them in their maven repository
+pub async fn verify_loader(loader: &LoaderVersion) -> Result<()> {
+    let profile: LoaderProfile = serde_json::from_slice(
+        &daedalus::download_file(&loader.url, None).await?,
+    )?;
+
+    for library in profile.libraries {
+        let Some(repository) = library.url else {
+            continue;
+        };
+        let path = daedalus::get_path_from_artifact(&library.name)?;
+        let url = format!("{}/{path}", repository.trim_end_matches('/'));
+
+        let published =
+            daedalus::download_file(&format!("{url}.sha1"), None).await?;
+        let published = String::from_utf8_lossy(&published);
+        let actual =
+            daedalus::get_hash(daedalus::download_file(&url, None).await?)
+                .await?;
+
+        // some .sha1 files are followed by the file name
+        if published.split_whitespace().next() != Some(actual.as_str()) {
+            return Err(ChecksumMismatch(library.name));
+        }
+        debug!("Verified {}", library.name);
+    }
+
+    Ok(())
+}
+
+#[derive(Deserialize, Debug, Clone)]
+/// The parts of a loader's launcher profile needed to verify it
+struct LoaderProfile {
+    #[serde(rename = "mainClass")]
+    main_class: String,
+    libraries: Vec<LoaderLibrary>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct LoaderLibrary {
+    /// The maven coordinates of the library
+    name: String,
+    /// The maven repository the library is downloaded from
+    url: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+/// A version of Minecraft that fabric supports
+struct GameVersion {
+    /// The version number of the game
+    pub version: String,
+    /// Whether the Minecraft version is stable or not
+    pub stable: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct LoaderVersionElement {
+    pub loader: MetaLoaderVersion,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct MetaLoaderVersion {
+    /// The separator to get the build number
+    pub separator: String,
+    /// The build number
+    pub build: u32,
+    /// The maven artifact
+    pub maven: String,
+    /// The version number of the fabric loader
+    pub version: String,
+    /// Whether the loader is stable or not
+    #[serde(default = "bool::default")]
+    pub stable: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    const FABRIC_LOADERS: &str = r#"[
+        {
+            "loader": {
+                "separator": ".",
+                "build": 14,
+                "maven": "net.fabricmc:fabric-loader:0.14.14",
+                "version": "0.14.14",
+                "stable": true
+            },
+            "intermediary": {
+                "maven": "net.fabricmc:intermediary:1.19.3",
+                "version": "1.19.3",
+                "stable": true
+            }
+        },
+        {
+            "loader": {
+                "separator": ".",
+                "build": 13,
+                "maven": "net.fabricmc:fabric-loader:0.14.13",
+                "version": "0.14.13",
+                "stable": false
+            }
+        }
+    ]"#;
+
+    const QUILT_LOADERS: &str = r#"[
+        {
+            "loader": {
+                "separator": "-",
+                "build": 0,
+                "maven": "org.quiltmc:quilt-loader:0.18.2-beta.1",
+                "version": "0.18.2-beta.1"
+            }
+        }
+    ]"#;
+
+    const UNSTABLE_FIRST_LOADERS: &str = r#"[
+        {
+            "loader": {
+                "separator": ".",
+                "build": 15,
+                "maven": "net.fabricmc:fabric-loader:0.14.15-beta.1",
+                "version": "0.14.15-beta.1",
+                "stable": false
+            }
+        },
+        {
+            "loader": {
+                "separator": ".",
+                "build": 14,
+                "maven": "net.fabricmc:fabric-loader:0.14.14",
+                "version": "0.14.14",
+                "stable": true
+            }
+        }
+    ]"#;
+
+    async fn serve(route: &str, body: &str) -> MockServer {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path(route))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_raw(body, "application/json"),
+            )
+            .mount(&server)
+            .await;
+        server
+    }
+
+    #[tokio::test]
+    async fn latest_fabric_loader() {
+        let server = serve("/versions/loader/1.19.3", FABRIC_LOADERS).await;
+
+        let version = get_latest_fabric(&server.uri(), "1.19.3", true)
+            .await
+            .unwrap();
+
+        assert_eq!(version.id, "0.14.14");
+        assert!(version.stable);
+        assert_eq!(
+            version.url,
+            format!(
+                "{}/versions/loader/1.19.3/0.14.14/profile/json",
+                server.uri()
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn missing_stable_field_is_unstable() {
+        let server = serve("/versions/loader/1.19.3", QUILT_LOADERS).await;
+
+        let version = get_latest_quilt(&server.uri(), "1.19.3", true)
+            .await
+            .unwrap();
+
+        assert_eq!(version.id, "0.18.2-beta.1");
+        assert!(!version.stable);
+    }
+
+    #[tokio::test]
+    async fn stable_loader_is_preferred() {
+        let server =
+            serve("/versions/loader/1.19.3", UNSTABLE_FIRST_LOADERS).await;
+
+        let stable = get_latest_loader(&server.uri(), "1.19.3", "fabric", true)
+            .await
+            .unwrap();
+        let newest =
+            get_latest_loader(&server.uri(), "1.19.3", "fabric", false)
+                .await
+                .unwrap();
+
+        assert_eq!(stable.id, "0.14.14");
+        assert_eq!(newest.id, "0.14.15-beta.1");
+    }
+
+    #[tokio::test]
+    async fn all_loaders_are_listed() {
+        let server = serve("/versions/loader/1.19.3", FABRIC_LOADERS).await;
+
+        let versions = list_loaders(&server.uri(), "1.19.3").await.unwrap();
+
+        let ids = versions.iter().map(|v| v.id.as_str()).collect::<Vec<_>>();
+        assert_eq!(ids, ["0.14.14", "0.14.13"]);
+        assert!(!versions[1].stable);
+    }
+
+    #[tokio::test]
+    async fn no_loader_is_an_error() {
+        let server = serve("/versions/loader/1.8", "[]").await;
+
+        let result = get_latest_legacy_fabric(&server.uri(), "1.8", true).await;
+
+        assert!(matches!(result, Err(MetaError("legacy fabric"))));
+    }
+
+    /// Serves a loader profile with a single library and its checksum
+    async fn serve_loader(sha1: &str) -> (MockServer, LoaderVersion) {
+        let server = MockServer::start().await;
+        let profile = format!(
+            r#"{{"mainClass": "net.fabricmc.loader.impl.launch.knot.KnotClient", "libraries": [{{"name": "net.fabricmc:fabric-loader:0.14.14", "url": "{}/maven/"}}]}}"#,
+            server.uri()
+        );
+        let jar = "/maven/net/fabricmc/fabric-loader/0.14.14/fabric-loader-0.14.14.jar";
+        for (route, body) in [
+            ("/profile/json", profile.as_str()),
+            (jar, "jar"),
+            (&format!("{jar}.sha1"), sha1),
+        ] {
+            Mock::given(method("GET"))
+                .and(path(route))
+                .respond_with(ResponseTemplate::new(200).set_body_string(body))
+                .mount(&server)
+                .await;
+        }
+
+        let version = LoaderVersion {
+            id: "0.14.14".into(),
+            url: format!("{}/profile/json", server.uri()),
+            stable: true,
+        };
+        (server, version)
+    }
+
+    #[tokio::test]
+    async fn matching_checksums_are_verified() {
+        // sha1 of "jar"
+        let sha1 =
+            "f92e777f4341930bad9b2422283c4680d00dbc06  fabric-loader.jar";
+        let (_server, version) = serve_loader(sha1).await;
+
+        verify_loader(&version).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn mismatching_checksum_is_an_error() {
+        let (_server, version) = serve_loader("0000").await;
+
+        let result = verify_loader(&version).await;
+
+        assert!(
+            matches!(result, Err(ChecksumMismatch(name)) if name == "net.fabricmc:fabric-loader:0.14.14")
+        );
+    }
+
+    #[tokio::test]
+    async fn invalid_loader_profile_is_an_error() {
+        let (_server, mut version) = serve_loader("0000").await;
+        check_loader_profile(&version).await.unwrap();
+
+        version.url.push_str("/missing");
+        let result = check_loader_profile(&version).await;
+
+        assert!(
+            matches!(result, Err(InvalidLoaderProfile { id, .. }) if id == "0.14.14")
+        );
+    }
+
+    #[test]
+    fn quilt_runs_fabric_mods() {
+        assert!(Loader::Quilt.runs("fabric"));
+        assert!(Loader::Quilt.runs("quilt"));
+        assert!(!Loader::Fabric.runs("quilt"));
+        assert!(!Loader::Fabric.runs("forge"));
+        assert!(!Loader::Forge.runs("fabric"));
+        assert!(!Loader::NeoForge.runs("forge"));
+    }
+
+    #[test]
+    fn manifest_names_are_read_back() {
+        for loader in [
+            Loader::Fabric,
+            Loader::Quilt,
+            Loader::LegacyFabric,
+            Loader::Forge,
+            Loader::NeoForge,
+        ] {
+            assert_eq!(
+                Loader::from_modrinth(loader.modrinth_name()),
+                Some(loader)
+            );
+        }
+        // older manifests hold the name of the theseus loader
+        assert_eq!(Loader::from_modrinth("Forge"), Some(Loader::Forge));
+    }
+}
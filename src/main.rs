@@ -1,271 +1,2006 @@
-mod auth;
-mod java;
-mod modpack;
-mod version;
-
-use crate::auth::{get_credentials, refresh_credentials};
-use crate::java::get_java_settings;
-use crate::modpack::get_metadata;
-use crate::version::MinecraftVersion;
-use crate::UklientError::MetaError;
-use auth::get_device_code;
-use clap::Parser;
-use daedalus::modded::LoaderVersion;
-use indicatif::ProgressStyle;
-use reqwest::Client;
-use std::ffi::OsString;
-use tracing::{debug, info, warn};
-
-use libium::HOME;
-use serde::{Deserialize, Serialize};
-use std::fs::File;
-use std::io::{BufReader, BufWriter};
+use chrono::{DateTime, NaiveDate, Utc};
+use clap::error::ErrorKind;
+use clap::parser::ValueSource;
+use clap::{
+    ArgAction, ArgGroup, CommandFactory, FromArgMatches, Parser, Subcommand,
+};
+use dialoguer::{Confirm, Select};
+use itertools::Itertools;
+use std::env::consts::{ARCH, OS};
+use std::future::Future;
+use std::num::{NonZeroU32, NonZeroUsize};
 use std::path::{Path, PathBuf};
-
-use once_cell::sync::Lazy;
+use std::pin::Pin;
+use std::process::ExitCode;
+use std::time::{Duration, Instant};
 use theseus::auth::Credentials;
 use theseus::data::{MemorySettings, WindowSize};
 use theseus::profile;
-use theseus::profile::Profile;
-use thiserror::Error;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::process::{Child, ChildStdout};
+use tokio::runtime;
 use tokio::sync::oneshot;
+use tracing::{debug, debug_span, error, info, warn, Instrument};
+use tracing_subscriber::filter::{LevelFilter, Targets};
+use tracing_subscriber::prelude::*;
+use uklient_rs::config::Config;
+use uklient_rs::gpu::Gpu;
+use uklient_rs::history::History;
+use uklient_rs::ipc::Event;
+use uklient_rs::java::{get_exact_java_settings, get_java_settings};
+use uklient_rs::loader::Loader;
+use uklient_rs::manifest::Manifest;
+use uklient_rs::modpack::{get_metadata, Channel, InstallOptions};
+use uklient_rs::modrinth::Modrinth;
+use uklient_rs::priority::Priority;
+use uklient_rs::profiles::Registry;
+use uklient_rs::state::State;
+use uklient_rs::version::{self, MinecraftVersion};
+use uklient_rs::UklientError::MetaError;
+use uklient_rs::{
+    connect_account, gpu, http, ipc, java, loader, manifest, memory,
+    merge_profile, modpack, modrinth, new_profile, offline_credentials, paths,
+    priority, profile_dir, read_credentials, run_profile, saved_accounts,
+    set_fullscreen, set_progress_template, telemetry, timings, update,
+    verify_profile, LoginOptions, Result, UklientError, CLIENT,
+    DEFAULT_RESOLUTION,
+};
 
-type Result<T> = std::result::Result<T, UklientError>;
+const DEFAULT_MAX_MEMORY: u32 = 4 * 1024;
+const DEFAULT_MODPACK_ID: &str = "ukupvp";
+const DEFAULT_GAME_VERSION: &str = "1.19.3";
+/// How long --smoke-test waits for the game if no launch timeout is given
+const DEFAULT_SMOKE_TEST_TIMEOUT: u64 = 5 * 60;
+/// Environment variables that change how the game runs, written by
+/// --export-env when they are set
+const EXPORTED_ENV: &[&str] = &[
+    "DRI_PRIME",
+    "__NV_PRIME_RENDER_OFFLOAD",
+    "__GLX_VENDOR_LIBRARY_NAME",
+    "__VK_LAYER_NV_optimus",
+    "JAVA_HOME",
+    "JAVA_TOOL_OPTIONS",
+];
+/// Logged by the game once it has finished loading and shows the main menu
+const READY_MARKERS: &[&str] = &["Sound engine started"];
 
-const FABRIC_META_URL: &str = "https://meta.fabricmc.net/v2";
-const QUILT_META_URL: &str = "https://meta.quiltmc.org/v3";
-const ONE_SEVENTEEN: MinecraftVersion = MinecraftVersion {
-    minor: 17,
-    patch: 0,
-};
-pub static STYLE_BYTE: Lazy<ProgressStyle> = Lazy::new(|| {
-    ProgressStyle::default_bar()
-        .template("{bytes_per_sec} [{bar:30}] {bytes}/{total_bytes}")
-        .expect("Progess bar template parse failure")
-        .progress_chars("#>-")
-});
-pub static CLIENT: Lazy<Client> = Lazy::new(Client::new);
-
-#[derive(Debug, Parser)]
+#[derive(Debug, Clone, Parser)]
 #[command(author, version, about, long_about = None)]
+// flags of different groups, only found together without a subcommand
+#[command(group(
+    ArgGroup::new("install_mode").args(["offline", "force_reinstall", "assets_only"])
+))]
+#[command(group(
+    ArgGroup::new("launch_mode").args(["no_launch", "smoke_test", "keep_open"])
+))]
 struct Args {
-    #[arg(long, default_value_t = String::from("ukupvp"), help = "specify the modpack to be downloaded")]
-    modpack_id: String,
-    #[arg(long, help = "always download java when launching")]
-    force_java_download: bool,
+    #[command(subcommand)]
+    action: Option<Action>,
+    #[command(flatten)]
+    modpack: ModpackArgs,
+    #[command(flatten)]
+    install: InstallArgs,
+    #[command(flatten)]
+    profile: ProfileArgs,
+    #[command(flatten)]
+    login: LoginArgs,
+    #[command(flatten)]
+    game: GameArgs,
     #[arg(long, help = "don't launch the game, only install the modpack")]
     no_launch: bool,
+    #[arg(
+        long,
+        value_name = "MODPACK_ID",
+        help = "list the versions of a modpack available for the game version and exit"
+    )]
+    list_versions: Option<String>,
+    #[arg(
+        long,
+        value_name = "MODPACK_ID",
+        help = "print the version, loader and files a modpack resolves to as json and exit"
+    )]
+    dump_metadata: Option<String>,
+    #[arg(
+        long,
+        value_name = "MODPACK_ID",
+        help = "keep the installed version of a modpack from being updated and exit"
+    )]
+    lock: Option<String>,
+    #[arg(long, help = "check if a newer version of uklient is available")]
+    check_updates: bool,
+    #[arg(
+        long,
+        help = "list the files of ~/.uklient/patches/<modpack> that would be copied over the profile, then exit"
+    )]
+    list_patches: bool,
+    #[arg(
+        long,
+        num_args = 2,
+        value_names = ["MODPACK_A", "MODPACK_B"],
+        help = "show how the mods of two installed modpacks differ and exit"
+    )]
+    diff: Option<Vec<String>>,
+    #[arg(
+        long,
+        value_name = "MODPACK_ID",
+        help = "download the missing or corrupted files of an installed modpack and exit"
+    )]
+    repair: Option<String>,
+    #[arg(long, help = "list the Java installations that were found and exit")]
+    list_java: bool,
+    #[arg(
+        long,
+        help = "print the platform, paths and Java installations for bug reports and exit"
+    )]
+    info: bool,
+    #[arg(
+        long,
+        value_enum,
+        value_name = "LOADER",
+        help = "list the versions of a loader available for the game version and exit"
+    )]
+    list_loaders: Option<Loader>,
+    #[arg(
+        long,
+        help = "check that the Fabric, Quilt, Modrinth and Mojang servers can be reached, then exit"
+    )]
+    self_test: bool,
+    /// Replaces DEFAULT_MAX_MEMORY, only set from the config
+    #[arg(skip)]
+    default_memory_mb: Option<u32>,
+    /// Only set from the config
+    #[arg(skip)]
+    min_memory: Option<u32>,
+    /// Only set from the config
+    #[arg(skip)]
+    fullscreen: Option<bool>,
+    /// Read once the modpack is known, for its own settings
+    #[arg(skip)]
+    config: Config,
+    #[arg(long, global = true, help = "don't ask for confirmation")]
+    yes: bool,
+    #[arg(
+        long,
+        global = true,
+        help = "number of threads used by the async runtime, 1 runs everything on the main thread"
+    )]
+    worker_threads: Option<NonZeroUsize>,
+    #[arg(
+        long,
+        global = true,
+        help = "don't color the output, also set by NO_COLOR"
+    )]
+    no_color: bool,
+    #[arg(
+        long,
+        global = true,
+        help = "don't show progress bars, log the progress instead"
+    )]
+    no_progress: bool,
+    #[arg(
+        long,
+        global = true,
+        value_name = "LEVEL",
+        default_value_t = LevelFilter::INFO,
+        help = "log level of theseus, e.g. off, warn or trace, independent of uklient's own logs"
+    )]
+    theseus_log_level: LevelFilter,
+    #[arg(
+        long,
+        global = true,
+        help = "log how long each phase of the install took"
+    )]
+    timings: bool,
+    #[arg(
+        long,
+        global = true,
+        value_name = "FILE",
+        help = "read the settings from this file instead of ~/.uklient/config.toml"
+    )]
+    config_path: Option<PathBuf>,
+    #[arg(
+        long,
+        global = true,
+        help = "fail instead of asking anything, for scripts and automation"
+    )]
+    non_interactive: bool,
+    #[arg(
+        long,
+        global = true,
+        value_name = "PATH",
+        help = "open a unix socket at this path sending the progress as json lines, for graphical frontends"
+    )]
+    ipc: Option<PathBuf>,
+    #[arg(
+        long,
+        global = true,
+        help = "under WSL, install the profiles in the Windows user folder"
+    )]
+    windows_home: bool,
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    let format = tracing_subscriber::fmt::format().with_target(false);
-    tracing_subscriber::fmt().event_format(format).init();
-    let args = Args::parse();
+/// The modpacks the default action, `install` and `launch` work on
+#[derive(Debug, Clone, clap::Args)]
+struct ModpackArgs {
+    #[arg(
+        long,
+        env = "UKLIENT_MODPACK_ID",
+        help = "specify the modpack to be downloaded, defaults to the last one installed. Can be repeated to install several modpacks without launching them"
+    )]
+    modpack_id: Vec<String>,
+}
 
-    let game_version = MinecraftVersion::parse("1.19.3")?;
-    let java_version: u8 = if game_version >= ONE_SEVENTEEN { 17 } else { 8 };
-    let java = get_java_settings(java_version, args.force_java_download).await;
+/// How the modpack is installed, by the default action and `install`
+#[derive(Debug, Clone, clap::Args)]
+struct InstallArgs {
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "also install the modpacks listed in this file, one id per line"
+    )]
+    modpack_list: Option<PathBuf>,
+    #[arg(
+        long,
+        alias = "mc-version",
+        env = "UKLIENT_GAME_VERSION",
+        value_parser = MinecraftVersion::parse,
+        help = "minecraft version the modpack is installed for [default: 1.19.3]"
+    )]
+    game_version: Option<MinecraftVersion>,
+    #[arg(long, help = "always download java when launching")]
+    force_java_download: bool,
+    #[arg(
+        long,
+        value_name = "BUILD",
+        help = "use this exact Java build, e.g. 17.0.9+9, instead of the latest one of the required version"
+    )]
+    java_version_exact: Option<String>,
+    #[arg(long, help = "update a locked modpack, removing its lock")]
+    unlock: bool,
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "install the exact modpack and mod versions of a manifest, e.g. a committed uklient-manifest.json"
+    )]
+    lockfile: Option<PathBuf>,
+    #[arg(long, help = "install a specific version of the modpack")]
+    pack_version: Option<String>,
+    #[arg(
+        long,
+        value_enum,
+        default_value_t,
+        help = "least stable modpack versions that can be installed"
+    )]
+    channel: Channel,
+    #[arg(
+        long,
+        value_name = "MODPACK_ID",
+        help = "reinstall the previously installed version of a modpack"
+    )]
+    rollback: Option<String>,
+    #[arg(
+        long,
+        value_name = "MODPACK_ID",
+        conflicts_with = "rollback",
+        help = "delete the mods of a profile and install it again, keeping the saves"
+    )]
+    force_reinstall: Option<String>,
+    #[arg(
+        long,
+        requires = "force_reinstall",
+        help = "also delete the mod configs when reinstalling"
+    )]
+    reset_config: bool,
+    #[arg(
+        long,
+        value_name = "KB/S",
+        help = "limit the combined speed of all downloads, in kilobytes per second"
+    )]
+    max_download_rate: Option<NonZeroU32>,
+    #[arg(
+        long,
+        help = "replace existing files with the ones from the modpack overrides"
+    )]
+    force_overrides: bool,
+    #[arg(
+        long = "skip-mod",
+        value_name = "NAME_OR_ID",
+        help = "don't install a mod from the modpack, can be repeated"
+    )]
+    skip_mods: Vec<String>,
+    #[arg(
+        long = "only-mod",
+        value_name = "NAME_OR_ID",
+        help = "only install this mod and its dependencies, can be repeated"
+    )]
+    only_mods: Vec<String>,
+    #[arg(
+        long,
+        help = "install the mods listed in ~/.uklient/denylist.txt anyway"
+    )]
+    ignore_denylist: bool,
+    #[arg(long, help = "don't install the mods the modpack marks as optional")]
+    no_optional: bool,
+    #[arg(
+        long,
+        conflicts_with_all = ["no_optional", "non_interactive"],
+        help = "choose which optional mods to install"
+    )]
+    choose_optional: bool,
+    #[arg(
+        long,
+        value_name = "DATE",
+        value_parser = parse_since,
+        help = "only update the mods with a version published after DATE (YYYY-MM-DD) or after the last install (last-install)"
+    )]
+    since: Option<Since>,
+    #[arg(
+        long,
+        help = "fail instead of warning when a mod doesn't support the loader"
+    )]
+    strict_compat: bool,
+    #[arg(
+        long,
+        help = "delete the mods the modpack dropped since the last install instead of moving them to mods/.old"
+    )]
+    prune: bool,
+    #[arg(
+        long,
+        help = "link identical mods between profiles instead of downloading them again"
+    )]
+    dedupe: bool,
+    #[arg(
+        long,
+        value_name = "TEMPLATE",
+        help = "indicatif template of the download progress bars, e.g. \"{bytes}/{total_bytes}\""
+    )]
+    progress_template: Option<String>,
+    #[arg(
+        long,
+        help = "keep installing when a mod fails to download and list the failures at the end"
+    )]
+    best_effort: bool,
+    #[arg(long, help = "also install the mods that only work on servers")]
+    include_server_mods: bool,
+    #[arg(
+        long,
+        help = "install the mods in this directory instead of the profile"
+    )]
+    mods_dir: Option<PathBuf>,
+    #[arg(
+        long,
+        help = "use Fabric when a Quilt modpack has no Quilt loader for the game version"
+    )]
+    allow_fabric_fallback: bool,
+    #[arg(
+        long,
+        value_name = "BOOL",
+        default_value_t = true,
+        action = ArgAction::Set,
+        help = "install the newest stable loader, only using a prerelease if there is no stable one"
+    )]
+    prefer_stable: bool,
+    #[arg(
+        long,
+        value_enum,
+        help = "install this loader instead of the one of the modpack"
+    )]
+    loader: Option<Loader>,
+    #[arg(
+        long,
+        value_enum,
+        help = "the loader to install when the modpack doesn't specify one [default: fabric]"
+    )]
+    default_loader: Option<Loader>,
+    #[arg(
+        long,
+        env = "UKLIENT_MODRINTH_API_URL",
+        help = "base url of the Modrinth API"
+    )]
+    modrinth_api_url: Option<String>,
+    #[arg(
+        long,
+        value_name = "DIR",
+        conflicts_with_all = ["rollback", "force_reinstall"],
+        help = "only download the mods and files of the modpack to this directory, without creating a profile or launching"
+    )]
+    assets_only: Option<PathBuf>,
+    #[arg(
+        long,
+        help = "keep the loader of the installed profile if it matches, only updating the mods"
+    )]
+    skip_loader_install: bool,
+    #[arg(
+        long,
+        help = "check the loader libraries against the checksums of their maven repository"
+    )]
+    verify_signatures: bool,
+}
 
-    let metadata =
-        get_metadata(&args.modpack_id, game_version.to_string().as_str())
-            .await?;
+/// The settings of the profile, kept for the next launches
+#[derive(Debug, Clone, clap::Args)]
+struct ProfileArgs {
+    #[arg(
+        long,
+        env = "UKLIENT_MAX_MEMORY",
+        help = "maximum memory allocated to the game, in megabytes"
+    )]
+    max_memory: Option<u32>,
+    #[arg(
+        long,
+        help = "fail instead of warning when the game is given more memory than the machine has"
+    )]
+    strict_memory: bool,
+    #[arg(
+        long,
+        value_name = "WIDTHxHEIGHT",
+        value_parser = parse_resolution,
+        help = "size of the game window, kept for the next launches"
+    )]
+    resolution: Option<WindowSize>,
+}
+
+/// The account the game is launched with
+#[derive(Debug, Clone, clap::Args)]
+struct LoginArgs {
+    #[arg(long, help = "log in again instead of using the saved credentials")]
+    force_relogin: bool,
+    #[arg(
+        long,
+        value_name = "USERNAME",
+        help = "use this saved account instead of the last one that logged in"
+    )]
+    account: Option<String>,
+    #[arg(
+        long,
+        conflicts_with_all = ["account", "force_relogin"],
+        value_parser = parse_username,
+        help = "play offline with this username instead of a Microsoft account"
+    )]
+    username: Option<String>,
+}
+
+/// How the game is launched, by the default action and `launch`
+#[derive(Debug, Clone, clap::Args)]
+struct GameArgs {
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        help = "warn if the game hasn't reached the main menu after this many seconds"
+    )]
+    launch_timeout: Option<u64>,
+    #[arg(
+        long,
+        requires = "launch_timeout",
+        help = "kill the game when it hasn't reached the main menu in time"
+    )]
+    kill_on_timeout: bool,
+    #[arg(
+        long,
+        help = "launch the game, then close it and exit once it reaches the main menu"
+    )]
+    smoke_test: bool,
+    #[arg(
+        long,
+        help = "opt out of the optional telemetry of the game (see the README)"
+    )]
+    no_telemetry: bool,
+    #[arg(
+        long,
+        visible_alias = "keep-launcher-open",
+        conflicts_with_all = ["non_interactive", "smoke_test"],
+        help = "ask to launch the game again, possibly with another account, once it exits"
+    )]
+    keep_open: bool,
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "write a shell script with the flags, Java and JVM arguments of the launch, without credentials"
+    )]
+    export_env: Option<PathBuf>,
+    #[arg(
+        long,
+        conflicts_with = "force_relogin",
+        help = "launch an installed modpack without connecting to the internet"
+    )]
+    offline: bool,
+    #[arg(long, value_enum, help = "OS priority of the game process")]
+    priority: Option<Priority>,
+    #[arg(
+        long,
+        value_enum,
+        help = "GPU used by the game on hybrid graphics laptops, linux only"
+    )]
+    gpu: Option<Gpu>,
+}
+
+fn main() -> ExitCode {
+    let args = parse_args();
+    let color = !args.no_color
+        && std::env::var_os("NO_COLOR").map_or(true, |v| v.is_empty());
+    console::set_colors_enabled(color);
+    console::set_colors_enabled_stderr(color);
+    let format = tracing_subscriber::fmt::format()
+        .with_target(false)
+        .with_ansi(color);
+    tracing_subscriber::registry()
+        .with(
+            tracing_subscriber::fmt::layer()
+                .event_format(format)
+                .with_filter(
+                    Targets::new()
+                        .with_default(LevelFilter::INFO)
+                        .with_target("theseus", args.theseus_log_level),
+                ),
+        )
+        .with(args.timings.then_some(timings::TimingLayer))
+        .init();
+    let show_timings = args.timings;
+
+    let mut runtime = match args.worker_threads.map(NonZeroUsize::get) {
+        Some(1) => runtime::Builder::new_current_thread(),
+        Some(n) => {
+            let mut builder = runtime::Builder::new_multi_thread();
+            builder.worker_threads(n);
+            builder
+        }
+        None => runtime::Builder::new_multi_thread(),
+    };
+
+    let result = runtime
+        .enable_all()
+        .build()
+        .map_err(UklientError::from)
+        .and_then(|runtime| {
+            runtime.block_on(async {
+                let result = run(args).await;
+                if let Err(e) = &result {
+                    ipc::emit(Event::Error {
+                        message: e.to_string(),
+                        exit_code: e.exit_code(),
+                    });
+                }
+                ipc::flush().await;
+                result
+            })
+        });
+    if show_timings {
+        timings::report();
+    }
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            error!("{e}");
+            ExitCode::from(e.exit_code())
+        }
+    }
+}
+
+/// Parses the command line. The flags of the default action are refused before
+/// a subcommand, which would ignore them
+fn parse_args() -> Args {
+    let mut command = Args::command();
+    let matches = command.get_matches_mut();
+    if let Some((name, _)) = matches.subcommand() {
+        let misplaced = matches.ids().find_map(|id| {
+            let arg = command.get_arguments().find(|a| a.get_id() == id)?;
+            let given = matches.value_source(id.as_str())
+                == Some(ValueSource::CommandLine);
+            (given && !arg.is_global_set()).then_some(arg)
+        });
+        if let Some(arg) = misplaced {
+            let flag = arg.get_long().unwrap_or_default();
+            command
+                .error(
+                    ErrorKind::ArgumentConflict,
+                    format!("--{flag} can't be used before `{name}`, give the flags of a subcommand after it"),
+                )
+                .exit();
+        }
+    }
+
+    Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit())
+}
+
+async fn run(mut args: Args) -> Result<()> {
+    // the flags of a subcommand replace the ones of the default action
+    match &args.action {
+        Some(Action::Install {
+            modpack,
+            install,
+            profile,
+            login,
+        }) => {
+            args.modpack = modpack.clone();
+            args.install = install.as_ref().clone();
+            args.profile = profile.clone();
+            args.login = login.clone();
+            args.no_launch = true;
+        }
+        Some(Action::Launch {
+            modpack,
+            profile,
+            login,
+            game,
+        }) => {
+            args.modpack = modpack.clone();
+            args.profile = profile.clone();
+            args.login = login.clone();
+            args.game = game.clone();
+        }
+        Some(Action::Remove {
+            modpack_id: Some(id),
+            ..
+        }) => args.modpack.modpack_id = vec![id.clone()],
+        _ => {}
+    }
+    let mut args = apply_config(args)?;
+    if let Some(path) = args.ipc.take() {
+        ipc::listen(&path)?;
+    }
+    uklient_rs::migrate_credentials();
+    if let Some(Action::Account { action }) = &args.action {
+        return manage_accounts(&args, action.clone()).await;
+    }
+    if let Some(path) = args.install.modpack_list.take() {
+        args.modpack.modpack_id.extend(read_modpack_list(&path)?);
+    }
+    if args.modpack.modpack_id.len() > 1
+        && matches!(args.action, None | Some(Action::Install { .. }))
+    {
+        return install_batch(args).await;
+    }
+    if let Some(rate) = args.install.max_download_rate {
+        http::set_max_download_rate(rate);
+    }
+    if let Some(template) = &args.install.progress_template {
+        set_progress_template(template);
+    }
+    if args.check_updates {
+        return update::check_updates().await;
+    }
+
+    if args.windows_home {
+        if !paths::is_wsl() {
+            warn!("--windows-home only works under WSL, ignoring it");
+        } else if let Some(home) = paths::windows_home() {
+            info!("Installing the game in {home:?}");
+            paths::set_data_dir(home.join(".uklient"));
+        } else {
+            warn!("Could not find the Windows user folder");
+        }
+    }
+
+    let game_version = args
+        .install
+        .game_version
+        .as_ref()
+        .expect("set from the config");
+    let java_version = java::required_java_version(game_version);
+
+    if args.self_test {
+        let modrinth_url = args
+            .install
+            .modrinth_api_url
+            .as_deref()
+            .unwrap_or(modrinth::DEFAULT_API_URL);
+        return self_test(modrinth_url).await;
+    }
+
+    if args.list_java {
+        java::list_java(java_version).await;
+        return Ok(());
+    }
+
+    if args.info {
+        return print_info(&args, game_version, java_version).await;
+    }
+
+    if let Some(Action::List { json }) = args.action {
+        return list_profiles(json);
+    }
+
+    if let Some(Action::Profiles { action }) = &args.action {
+        return manage_profiles(&args, action.clone()).await;
+    }
+
+    if let Some(loader) = args.list_loaders {
+        let game_version = game_version.to_string();
+        for version in
+            loader::list_loader_versions(loader, &game_version).await?
+        {
+            let stable = if version.stable { " (stable)" } else { "" };
+            println!("{}{stable}", version.id);
+        }
+        return Ok(());
+    }
+
+    if let Some([a, b]) = args.diff.as_deref() {
+        let ((_, a), (_, b)) = (Manifest::find(a)?, Manifest::find(b)?);
+        let diff = manifest::diff(&a, &b);
+        for installed in diff.only_a {
+            println!("only in {}: {}", a.modpack_id, installed.filename());
+        }
+        for installed in diff.only_b {
+            println!("only in {}: {}", b.modpack_id, installed.filename());
+        }
+        for (mod_a, mod_b) in diff.changed {
+            println!(
+                "different versions: {} -> {}",
+                mod_a.filename(),
+                mod_b.filename()
+            );
+        }
+        return Ok(());
+    }
+
+    if let Some(id) = &args.repair {
+        let (profile_dir, manifest) = Manifest::find(id)?;
+        let mods_dir = profile_dir.join("mods");
+        return interruptible(
+            modpack::repair(&profile_dir, &manifest, !args.no_progress),
+            &[mods_dir],
+        )
+        .await;
+    }
+
+    let modrinth = Modrinth::new(
+        args.install
+            .modrinth_api_url
+            .as_deref()
+            .unwrap_or(modrinth::DEFAULT_API_URL),
+    )?;
+
+    if let Some(id) = &args.list_versions {
+        let game_version = game_version.to_string();
+        let versions =
+            modpack::list_versions(&modrinth, id, &game_version).await?;
+        if versions.is_empty() {
+            println!("{id} has no version for Minecraft {game_version}");
+        }
+        for version in versions {
+            println!(
+                "{} ({}) - Minecraft {} - {}",
+                version.name,
+                version.version_number,
+                version.game_versions.join(", "),
+                version.loaders.join(", ")
+            );
+        }
+        return Ok(());
+    }
+
+    if let Some(id) = &args.lock {
+        let (dir, _) = Manifest::find(id)?;
+        manifest::set_locked(&dir, true)?;
+        info!("Locked {id}, it won't be updated until --unlock is used");
+        return Ok(());
+    }
+
+    let mut state = State::load()?;
+    let modpack_id = args
+        .install
+        .rollback
+        .as_ref()
+        .or(args.install.force_reinstall.as_ref())
+        .or(args.dump_metadata.as_ref())
+        .or(args.modpack.modpack_id.first())
+        .or(state.last_modpack_id.as_ref())
+        .map_or(DEFAULT_MODPACK_ID, String::as_str)
+        .to_string();
+    let modpack_id = modpack_id.as_str();
+    let settings = args.config.profile(modpack_id);
+    args.profile.max_memory = args.profile.max_memory.or(settings.max_memory);
+    args.min_memory = settings.min_memory;
+    args.profile.resolution = args.profile.resolution.or(settings.resolution);
+    args.fullscreen = settings.fullscreen;
+    if args.list_patches {
+        return modpack::apply_patches(modpack_id, Path::new(""), true);
+    }
+    match args.action {
+        Some(Action::Launch { .. }) => {
+            let (dir, _) = Manifest::find(modpack_id)?;
+            return launch_installed(
+                &args,
+                modpack_id,
+                &dir,
+                args.game.offline,
+            )
+            .await;
+        }
+        Some(Action::Remove { unlock, .. }) => {
+            let (dir, _) = Manifest::find(modpack_id)?;
+            return remove_profile(&args, modpack_id, &dir, unlock).await;
+        }
+        _ if args.game.offline => {
+            let (dir, _) = Manifest::find(modpack_id)?;
+            return launch_installed(&args, modpack_id, &dir, true).await;
+        }
+        _ => {}
+    }
+    match version::check_release(game_version).await {
+        Err(UklientError::ReqwestError(e)) => {
+            warn!("Could not check that Minecraft {game_version} exists: {e}")
+        }
+        result => result?,
+    }
+    let installed_dir = Manifest::find(modpack_id).ok().map(|(dir, _)| dir);
+    // --assets-only doesn't touch the installed profile
+    let profile = installed_dir
+        .as_ref()
+        .filter(|_| args.install.assets_only.is_none());
+    if let Some(dir) = profile {
+        if manifest::is_locked(dir) && !args.install.unlock {
+            if args.install.force_reinstall.is_some() {
+                return Err(UklientError::ProfileLocked(modpack_id.into()));
+            }
+            info!("{modpack_id} is locked, launching it without updating");
+            if args.no_launch {
+                return Ok(());
+            }
+            return launch_installed(&args, modpack_id, dir, false).await;
+        } else if args.install.unlock {
+            manifest::set_locked(dir, false)?;
+            info!("Unlocked {modpack_id}");
+        }
+    }
+    modpack::validate_modpack_id(&modrinth, modpack_id).await?;
+    let mut history = History::load(modpack_id)?;
+    let cwd = std::env::current_dir()?;
+    let mut options = InstallOptions {
+        pack_version: args.install.pack_version.clone(),
+        channel: args.install.channel,
+        loader: args.install.loader,
+        default_loader: args.install.default_loader.unwrap_or_default(),
+        allow_fabric_fallback: args.install.allow_fabric_fallback,
+        prefer_stable: args.install.prefer_stable,
+        force_overrides: args.install.force_overrides,
+        skip_mods: args.install.skip_mods.clone(),
+        denylist: if args.install.ignore_denylist {
+            Vec::new()
+        } else {
+            modpack::read_denylist()?
+        },
+        only_mods: args.install.only_mods.clone(),
+        no_optional: args.install.no_optional,
+        choose_optional: args.install.choose_optional,
+        excluded_optional: Vec::new(),
+        strict_compat: args.install.strict_compat,
+        since: None,
+        previous_mods: Vec::new(),
+        pinned: None,
+        prune: args.install.prune,
+        dedupe: args.install.dedupe,
+        best_effort: args.install.best_effort,
+        installed_loader: None,
+        include_server_mods: args.install.include_server_mods,
+        mods_dir: args.install.mods_dir.as_ref().map(|dir| cwd.join(dir)),
+    };
+    if let Some(path) = &args.install.lockfile {
+        let lock = Manifest::load_file(path)?;
+        if lock.modpack_id != modpack_id {
+            warn!("The lockfile is for {}, not {modpack_id}", lock.modpack_id);
+        }
+        options.pack_version =
+            options.pack_version.or(Some(lock.version_id.clone()));
+        options.pinned = Some(lock.mods);
+    }
+    if args.install.rollback.is_some() {
+        let previous =
+            history.rollback().ok_or(MetaError("previous modpack"))?;
+        info!("Rolling back {modpack_id} to version {previous}");
+        options.pack_version = Some(previous);
+    }
+
+    if args.dump_metadata.is_some() {
+        let resolved = modpack::resolve(
+            &modrinth,
+            modpack_id,
+            game_version.to_string().as_str(),
+            &options,
+        )
+        .await?;
+        println!("{}", serde_json::to_string_pretty(&resolved)?);
+        return Ok(());
+    }
+
+    // no java, loader, profile or account, only the files of the modpack
+    if let Some(dir) = &args.install.assets_only {
+        tokio::fs::create_dir_all(dir).await?;
+        let mods_dir =
+            options.mods_dir.clone().unwrap_or_else(|| dir.join("mods"));
+        let installed = interruptible(
+            modpack::install_modpack(
+                &modrinth,
+                dir,
+                modpack_id,
+                game_version.to_string(),
+                &options,
+            ),
+            &[mods_dir, dir.join("resourcepacks")],
+        )
+        .await?;
+        if !installed.failed.is_empty() {
+            return Err(UklientError::DownloadsFailed(installed.failed.len()));
+        }
+        info!("Downloaded {modpack_id} to {}", dir.display());
+        return Ok(());
+    }
+
+    if args.install.skip_loader_install {
+        if let Some(dir) = &installed_dir {
+            // theseus launches NeoForge as Forge, the manifest tells them apart
+            let loader = Manifest::load(dir)
+                .ok()
+                .and_then(|manifest| Loader::from_modrinth(&manifest.loader));
+            let profile = profile::get(dir).await?;
+            options.installed_loader = loader.zip(profile.map(|p| p.metadata));
+        }
+    }
+
+    java::check_platform()?;
+    ipc::emit(Event::Phase { phase: "java" });
+    let java = interruptible(
+        async {
+            match &args.install.java_version_exact {
+                Some(build) => {
+                    get_exact_java_settings(
+                        build,
+                        args.install.force_java_download,
+                        !args.no_progress,
+                    )
+                    .await
+                }
+                None => Ok(get_java_settings(
+                    java_version,
+                    args.install.force_java_download,
+                    !args.no_progress,
+                )
+                .await),
+            }
+        },
+        &[paths::tmp_dir()],
+    )
+    .instrument(debug_span!("java"))
+    .await?;
+
+    ipc::emit(Event::Phase { phase: "metadata" });
+    let (metadata, installed_loader) = get_metadata(
+        &modrinth,
+        modpack_id,
+        game_version.to_string().as_str(),
+        &options,
+    )
+    .instrument(debug_span!("metadata fetch"))
+    .await?;
     debug!(
         "Found {} version {:?} on Minecraft {}",
         metadata.loader, metadata.loader_version, game_version
     );
+    if args.install.verify_signatures {
+        if let Some(loader_version) = &metadata.loader_version {
+            loader::verify_loader(loader_version).await?;
+            info!("Verified the checksums of {}", metadata.loader);
+        }
+    }
+
+    let loader = installed_loader.modrinth_name().to_string();
+    let loader_version = metadata.loader_version.as_ref().map(|v| v.id.clone());
 
-    // grr theseus
-    let fixed_name = &metadata.name.replace(' ', "_");
-    let base_path: PathBuf = HOME.join(".uklient").join(fixed_name);
+    let base_path = profile_dir(&metadata);
+    let mods_dir = options
+        .mods_dir
+        .clone()
+        .unwrap_or_else(|| base_path.join("mods"));
+    if args.install.force_reinstall.is_some() {
+        if !args.yes && args.non_interactive {
+            return Err(UklientError::NeedsInteraction("--force-reinstall"));
+        }
+        let confirmed = args.yes
+            || Confirm::new()
+                .with_prompt(format!(
+                    "Delete the mods{} of {modpack_id} and reinstall it?",
+                    if args.install.reset_config {
+                        " and configs"
+                    } else {
+                        ""
+                    }
+                ))
+                .interact()?;
+        if !confirmed {
+            info!("Nothing was reinstalled");
+            return Ok(());
+        }
+        modpack::wipe(&base_path, &mods_dir, args.install.reset_config).await?;
+    }
     tokio::fs::create_dir_all(&base_path).await?;
 
-    let mc_profile = Profile {
-        path: base_path.clone(),
-        metadata,
-        java: Some(java),
-        memory: Some(MemorySettings {
-            maximum: (4 * 1024) as u32,
-            ..MemorySettings::default()
-        }),
-        resolution: Some(WindowSize(1280, 720)),
-        hooks: None,
+    // settings from a previous install are kept unless overridden
+    let existing = profile::get(&base_path).await?;
+    let existing_memory = existing.as_ref().and_then(|p| p.memory);
+
+    let max_memory = if let Some(m) = args.profile.max_memory {
+        info!("Using {m} MB of memory, as set by --max-memory or the config");
+        m
+    } else if let Some(m) = existing_memory.map(|memory| memory.maximum) {
+        info!("Using {m} MB of memory, as previously set");
+        m
+    } else if let Some(m) = args.default_memory_mb {
+        info!("Using {m} MB of memory, the default from the config");
+        m
+    } else {
+        info!("Using the default {DEFAULT_MAX_MEMORY} MB of memory");
+        DEFAULT_MAX_MEMORY
+    };
+
+    memory::check_max_memory(max_memory, args.profile.strict_memory)?;
+    let memory = MemorySettings {
+        maximum: max_memory,
+        minimum: args
+            .min_memory
+            .or_else(|| existing_memory.and_then(|memory| memory.minimum)),
     };
 
-    profile::add(mc_profile).await?;
-    let cred = connect_account().await?;
+    let resolution = args
+        .profile
+        .resolution
+        .or_else(|| existing.as_ref().and_then(|p| p.resolution))
+        .unwrap_or(DEFAULT_RESOLUTION);
+
+    let mc_profile = new_profile(
+        base_path.clone(),
+        metadata,
+        java,
+        memory,
+        resolution,
+        options.mods_dir.as_deref(),
+    );
+
+    ipc::emit(Event::Phase { phase: "profile" });
+    async {
+        if existing.is_some() {
+            profile::edit(&base_path, |p| {
+                merge_profile(p, mc_profile.clone());
+                async { Ok(()) }
+            })
+            .await
+        } else {
+            profile::add(mc_profile).await
+        }
+    }
+    .instrument(debug_span!("profile add"))
+    .await?;
+    if let Some(fullscreen) = args.fullscreen {
+        set_fullscreen(&base_path, fullscreen)?;
+    }
+    let cred = credentials(&args).await?;
     info!("Connected account {}", cred.username);
 
-    modpack::install_modpack(
-        &base_path,
-        &args.modpack_id,
-        game_version.to_string(),
+    // a new version of the modpack is installed in a new profile, the last
+    // install is the most recent one of the modpack
+    match Manifest::find(modpack_id) {
+        Ok((_, manifest)) => {
+            options.since = match args.install.since {
+                Some(Since::Date(date)) => Some(date),
+                Some(Since::LastInstall) => manifest.installed_at,
+                None => None,
+            };
+            options.previous_mods = manifest.mods;
+            options.excluded_optional = manifest.excluded_optional;
+        }
+        Err(_) if args.install.since.is_some() => {
+            warn!("{modpack_id} wasn't installed before, ignoring --since");
+        }
+        Err(_) => {}
+    }
+
+    ipc::emit(Event::Phase { phase: "mods" });
+    let installed = interruptible(
+        modpack::install_modpack(
+            &modrinth,
+            &base_path,
+            modpack_id,
+            game_version.to_string(),
+            &options,
+        ),
+        &[mods_dir, base_path.join("resourcepacks")],
     )
+    .instrument(debug_span!("mod downloads"))
     .await?;
+    let manifest = Manifest {
+        modpack_id: modpack_id.into(),
+        version_id: installed.version_id.clone(),
+        minecraft_version: game_version.to_string(),
+        loader,
+        loader_version,
+        mods: installed.mods,
+        installed_at: Some(Utc::now()),
+        excluded_optional: installed.excluded_optional,
+    };
+    manifest.save(&base_path)?;
+    let mut registry = Registry::load()?;
+    registry.register(&base_path, &manifest);
+    registry.save()?;
+    modpack::apply_patches(modpack_id, &base_path, false)?;
+    verify_profile(&base_path).await?;
+    history.push(installed.version_id);
+    history.save(modpack_id)?;
+    state.last_modpack_id = Some(modpack_id.into());
+    state.save()?;
+    if !installed.failed.is_empty() {
+        warn!(
+            "These mods could not be downloaded: {}",
+            installed.failed.join(", ")
+        );
+        return Err(UklientError::DownloadsFailed(installed.failed.len()));
+    }
     info!("Sucessfully installed modpack");
 
     if args.no_launch {
         return Ok(());
     }
 
-    let process = profile::run(&base_path, &cred).await?;
-    if let Some(pid) = process.id() {
-        info!("PID: {pid}");
-    } else {
-        warn!("NO PID? no bitches");
+    launch(&args, &base_path, &cred).await
+}
+
+/// Launches the game, then with --keep-open asks to launch it again until the
+/// user quits
+async fn launch(
+    args: &Args,
+    base_path: &Path,
+    cred: &Credentials,
+) -> Result<()> {
+    let mut cred = cred.clone();
+    loop {
+        launch_once(args, base_path, &cred).await?;
+        if !args.game.keep_open {
+            return Ok(());
+        }
+
+        let current = cred.username.clone();
+        let others = saved_accounts()
+            .into_iter()
+            .filter(|account| *account != current)
+            .collect::<Vec<_>>();
+        let mut items = vec![format!("Launch again as {current}")];
+        items.extend(others.iter().map(|a| format!("Launch as {a}")));
+        if !args.game.offline {
+            items.push("Log in with another account".into());
+        }
+        items.push("Quit".into());
+
+        let choice = Select::new()
+            .with_prompt("The game exited")
+            .items(&items)
+            .default(0)
+            .interact()?;
+        let account = match choice {
+            // offline credentials are never saved, and there is nothing to
+            // refresh without a connection
+            0 if args.login.username.is_some() || args.game.offline => continue,
+            0 => current,
+            i if i <= others.len() => others[i - 1].clone(),
+            i if i == items.len() - 1 => return Ok(()),
+            _ => {
+                let options = LoginOptions {
+                    force_relogin: true,
+                    account: None,
+                    non_interactive: false,
+                };
+                cred = connect_account(&options).await?;
+                continue;
+            }
+        };
+
+        cred = if args.game.offline {
+            read_credentials(Some(&account))?
+                .ok_or(UklientError::NotCached("account"))?
+        } else {
+            let options = LoginOptions {
+                account: Some(account),
+                ..login_options(args)
+            };
+            connect_account(&options).await?
+        };
+    }
+}
+
+async fn launch_once(
+    args: &Args,
+    base_path: &Path,
+    cred: &Credentials,
+) -> Result<()> {
+    if args.game.no_telemetry {
+        telemetry::disable_telemetry(base_path)?;
+        info!("Opted out of the optional telemetry");
+    }
+
+    if let Some(path) = &args.game.export_env {
+        export_env(path, base_path, args.game.gpu).await?;
+        info!("Wrote the launch environment to {}", path.display());
+    }
+
+    ipc::emit(Event::Phase { phase: "launch" });
+    let mut process = match args.game.gpu {
+        Some(gpu) => gpu::run_with_gpu(base_path, cred, gpu).await?,
+        None => run_profile(base_path, cred).await?,
+    };
+    if let Err(e) = manifest::mark_launched(base_path) {
+        debug!("Could not record the launch of {base_path:?}: {e}");
+    }
+    let Some(pid) = process.id() else {
+        // tokio only forgets the pid of a process once it has exited
+        let status = process.try_wait()?;
+        debug!("No PID for {base_path:?}, exit status: {status:?}");
+        return Err(UklientError::EarlyExit(
+            status.map_or_else(|| "unknown status".into(), |s| s.to_string()),
+        ));
+    };
+    info!("PID: {pid}");
+    ipc::emit(Event::Launched { pid });
+    if let Some(priority) = args.game.priority {
+        priority::set_priority(pid, priority).await;
+    }
+
+    if args.game.smoke_test {
+        let timeout = args
+            .game
+            .launch_timeout
+            .unwrap_or(DEFAULT_SMOKE_TEST_TIMEOUT);
+        return smoke_test(process, timeout).await;
     }
 
-    process.wait_with_output().await?;
+    // read for the whole run so that a chatty game can't fill the pipes
+    let (ready_tx, ready_rx) = oneshot::channel();
+    if let Some(stdout) = process.stdout.take() {
+        tokio::spawn(watch_output(stdout, ready_tx));
+    }
+    if let Some(mut stderr) = process.stderr.take() {
+        tokio::spawn(async move {
+            tokio::io::copy(&mut stderr, &mut tokio::io::sink()).await
+        });
+    }
+
+    if let Some(timeout) = args.game.launch_timeout {
+        let ready =
+            tokio::time::timeout(Duration::from_secs(timeout), ready_rx);
+        tokio::select! {
+            status = process.wait() => {
+                status?;
+            }
+            ready = ready => match ready {
+                Ok(Ok(())) => debug!("The game reached the main menu"),
+                // the output was closed, the game is exiting
+                Ok(Err(_)) => {}
+                Err(_) => {
+                    warn!("The game did not reach the main menu within {timeout} seconds");
+                    if args.game.kill_on_timeout {
+                        process.kill().await?;
+                        return Err(UklientError::LaunchTimeout(timeout));
+                    }
+                }
+            }
+        }
+    }
+
+    process.wait().await?;
     info!("Goodbye!");
 
     Ok(())
 }
 
-pub async fn get_latest_fabric(mc_version: &String) -> Result<LoaderVersion> {
-    let downloaded = daedalus::download_file(
-        format!("{FABRIC_META_URL}/versions/loader/{mc_version}").as_str(),
-        None,
-    )
-    .await?;
+/// Reads the output of the game until it exits, telling `ready` once it has
+/// reached the main menu
+async fn watch_output(stdout: ChildStdout, ready: oneshot::Sender<()>) {
+    let mut ready = Some(ready);
+    let mut lines = BufReader::new(stdout).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        if READY_MARKERS.iter().any(|marker| line.contains(marker)) {
+            if let Some(ready) = ready.take() {
+                let _ = ready.send(());
+            }
+        }
+    }
+}
 
-    let versions: Vec<LoaderVersionElement> =
-        serde_json::from_slice(&downloaded)?;
-    let latest = versions.get(0).ok_or(MetaError("fabric"))?.loader.clone();
-    let manifest_url = format!(
-        "{}/versions/loader/{}/{}/profile/json",
-        FABRIC_META_URL, mc_version, latest.version
+/// Writes a shell script describing how the profile is about to be launched,
+/// without the account credentials
+async fn export_env(
+    path: &Path,
+    base_path: &Path,
+    gpu: Option<Gpu>,
+) -> Result<()> {
+    let quote = |s: &str| format!("'{}'", s.replace('\'', r"'\''"));
+    let profile = profile::get(base_path)
+        .await?
+        .ok_or(UklientError::NotCached("the profile"))?;
+    let java = profile.java.unwrap_or_default();
+    let java_path = java
+        .install
+        .map_or_else(|| "java".into(), |p| p.display().to_string());
+
+    let mut script = String::from("#!/bin/sh\n");
+    script.push_str(&format!(
+        "# exported by uklient {} on {OS} {ARCH}\n",
+        env!("CARGO_PKG_VERSION")
+    ));
+    let command = std::env::args().map(|a| quote(&a)).join(" ");
+    script.push_str(&format!("# command: {command}\n"));
+    if let Ok(manifest) = Manifest::load(base_path) {
+        script.push_str(&format!(
+            "# modpack: {} {}, Minecraft {}, {} {}\n",
+            manifest.modpack_id,
+            manifest.version_id,
+            manifest.minecraft_version,
+            manifest.loader,
+            manifest.loader_version.as_deref().unwrap_or("")
+        ));
+    }
+    script.push_str(&format!("# profile: {}\n", base_path.display()));
+    script.push_str("# account: <redacted>\n");
+    script.push_str(
+        "# the game arguments are generated by theseus when launching\n\n",
     );
+    let gpu_env = gpu.map(gpu::gpu_env).unwrap_or_default();
+    for key in EXPORTED_ENV {
+        let value = match gpu_env.iter().find(|(k, _)| k == key) {
+            Some((_, value)) => value.map(String::from),
+            None => std::env::var(key).ok(),
+        };
+        if let Some(value) = value {
+            script.push_str(&format!("export {key}={}\n", quote(&value)));
+        }
+    }
 
-    Ok(LoaderVersion {
-        id: latest.version,
-        stable: latest.stable,
-        url: manifest_url,
-    })
+    let mut jvm_args = Vec::new();
+    if let Some(memory) = profile.memory {
+        jvm_args.push(format!("-Xmx{}M", memory.maximum));
+    }
+    jvm_args.extend(java.extra_arguments.unwrap_or_default());
+    script.push_str(&format!(
+        "cd {}\n{} {}\n",
+        quote(&base_path.display().to_string()),
+        quote(&java_path),
+        jvm_args.iter().map(|a| quote(a)).join(" ")
+    ));
+
+    std::fs::write(path, script)?;
+    Ok(())
 }
 
-pub async fn get_latest_quilt(mc_version: &String) -> Result<LoaderVersion> {
-    let downloaded = daedalus::download_file(
-        format!("{QUILT_META_URL}/versions/loader/{mc_version}").as_str(),
-        None,
-    )
-    .await?;
+/// Installs each of the modpacks of `args` without launching them, going on
+/// after failures
+async fn install_batch(args: Args) -> Result<()> {
+    let mut failed = 0;
+    let mut results = Vec::new();
+    for modpack_id in &args.modpack.modpack_id {
+        info!("Installing {modpack_id}");
+        let mut single = args.clone();
+        single.modpack.modpack_id = vec![modpack_id.clone()];
+        single.no_launch = true;
 
-    let versions: Vec<LoaderVersionElement> =
-        serde_json::from_slice(&downloaded)?;
-    let latest = versions.get(0).ok_or(MetaError("quilt"))?.loader.clone();
-    let manifest_url = format!(
-        "{}/versions/loader/{}/{}/profile/json",
-        QUILT_META_URL, mc_version, latest.version
-    );
+        // boxed, run() calls this function
+        let install: Pin<Box<dyn Future<Output = Result<()>>>> =
+            Box::pin(run(single));
+        let result = install.await;
+        if let Err(e) = &result {
+            error!("Could not install {modpack_id}: {e}");
+            failed += 1;
+        }
+        results.push((modpack_id, result));
+    }
+
+    for (modpack_id, result) in results {
+        match result {
+            Ok(()) => info!("{modpack_id}: installed"),
+            Err(e) => warn!("{modpack_id}: {e}"),
+        }
+    }
+    if failed > 0 {
+        return Err(UklientError::BatchFailed {
+            failed,
+            total: args.modpack.modpack_id.len(),
+        });
+    }
+    Ok(())
+}
 
-    Ok(LoaderVersion {
-        id: latest.version,
-        stable: latest.stable,
-        url: manifest_url,
-    })
+/// Reads the modpack ids of a --modpack-list file, ignoring empty lines and
+/// `#` comments
+fn read_modpack_list(path: &Path) -> Result<Vec<String>> {
+    Ok(std::fs::read_to_string(path)?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(String::from)
+        .collect())
 }
 
-async fn connect_account() -> Result<Credentials> {
-    let credentials_path = Path::new("./credentials.json");
+/// Checks that the servers uklient depends on can be reached, printing how
+/// long each one took to answer
+async fn self_test(modrinth_url: &str) -> Result<()> {
+    let endpoints = [
+        (
+            "Fabric meta",
+            format!("{}/versions/game", loader::FABRIC_META_URL),
+        ),
+        (
+            "Quilt meta",
+            format!("{}/versions/game", loader::QUILT_META_URL),
+        ),
+        ("Modrinth", modrinth_url.to_string()),
+        ("Mojang manifest", version::MOJANG_MANIFEST_URL.to_string()),
+    ];
 
-    if credentials_path.try_exists()? {
-        let credentials: Result<Credentials> = {
-            let file = File::open(credentials_path)?;
-            let creds: Credentials =
-                serde_json::from_reader(BufReader::new(file))?;
+    let mut failed = 0;
+    for (name, url) in endpoints {
+        let start = Instant::now();
+        let response = CLIENT.get(&url).send().await;
+        let elapsed = start.elapsed().as_millis();
+        match response.and_then(|r| r.error_for_status()) {
+            Ok(response) => {
+                println!("{name}: ok ({}) in {elapsed} ms", response.status())
+            }
+            Err(e) => {
+                println!("{name}: failed after {elapsed} ms: {e}");
+                failed += 1;
+            }
+        }
+    }
 
-            refresh_credentials(creds).await
-        };
+    if failed > 0 {
+        return Err(UklientError::SelfTestFailed(failed));
+    }
+    Ok(())
+}
 
-        if let Ok(creds) = credentials {
-            return Ok(creds);
+/// Deletes the profile `name` in `dir`, from theseus, the registry and the
+/// disk
+async fn remove_profile(
+    args: &Args,
+    name: &str,
+    dir: &Path,
+    unlock: bool,
+) -> Result<()> {
+    if manifest::is_locked(dir) && !unlock {
+        return Err(UklientError::ProfileLocked(name.into()));
+    }
+    if !args.yes && args.non_interactive {
+        return Err(UklientError::NeedsInteraction("removing a profile"));
+    }
+    let confirmed = args.yes
+        || Confirm::new()
+            .with_prompt(format!(
+                "Delete {name} and everything in {}?",
+                dir.display()
+            ))
+            .interact()?;
+    if !confirmed {
+        info!("Nothing was removed");
+        return Ok(());
+    }
+
+    profile::remove(dir).await?;
+    tokio::fs::remove_dir_all(dir).await?;
+    let mut registry = Registry::load()?;
+    registry.forget(dir);
+    registry.save()?;
+    info!("Removed {name}");
+    Ok(())
+}
+
+/// Runs an `account` subcommand
+async fn manage_accounts(args: &Args, action: AccountAction) -> Result<()> {
+    match action {
+        AccountAction::List => {
+            let default = read_credentials(None)?.map(|c| c.username);
+            let accounts = saved_accounts();
+            if accounts.is_empty() {
+                println!("No saved account");
+            }
+            for account in accounts {
+                let id = read_credentials(Some(&account))?
+                    .map(|c| c.id.to_string())
+                    .unwrap_or_default();
+                let marker = if default.as_ref() == Some(&account) {
+                    "*"
+                } else {
+                    " "
+                };
+                println!("{marker} {account} ({id})");
+            }
+        }
+        AccountAction::Add => {
+            let cred = connect_account(&LoginOptions {
+                force_relogin: true,
+                account: None,
+                non_interactive: args.non_interactive,
+            })
+            .await?;
+            info!("Added {}, it is now the default account", cred.username);
+        }
+        AccountAction::Remove { account } => {
+            let cred = uklient_rs::remove_account(&account)?;
+            info!("Removed {}", cred.username);
+        }
+        AccountAction::Use { account } => {
+            let cred = uklient_rs::use_account(&account)?;
+            info!("{} is now the default account", cred.username);
         }
     }
+    Ok(())
+}
+
+/// Runs a `profiles` subcommand
+async fn manage_profiles(args: &Args, action: ProfilesAction) -> Result<()> {
+    let mut registry = Registry::load()?;
+    match action {
+        ProfilesAction::List { json } => list_profiles(json),
+        ProfilesAction::Launch {
+            name,
+            profile,
+            login,
+            game,
+        } => {
+            let dir = registry.get(&name)?.path.clone();
+            let args = Args {
+                profile,
+                login,
+                game,
+                ..args.clone()
+            };
+            launch_installed(&args, &name, &dir, args.game.offline).await
+        }
+        ProfilesAction::Rename { name, new_name } => {
+            registry.rename(&name, &new_name).await?;
+            registry.save()?;
+            info!("Renamed {name} to {new_name}");
+            Ok(())
+        }
+        ProfilesAction::Duplicate { name, new_name } => {
+            let dir = registry.duplicate(&name, &new_name).await?;
+            registry.save()?;
+            info!("Copied {name} to {}", dir.display());
+            Ok(())
+        }
+        ProfilesAction::Delete { name, unlock } => {
+            let dir = registry.get(&name)?.path.clone();
+            remove_profile(args, &name, &dir, unlock).await
+        }
+    }
+}
+
+/// Prints the installed profiles, as a table or as a json array
+fn list_profiles(json: bool) -> Result<()> {
+    let mut profiles = Registry::load()?.summaries();
+    profiles.sort_by(|a, b| a.name.cmp(&b.name));
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&profiles)?);
+        return Ok(());
+    }
+    if profiles.is_empty() {
+        println!("No profile installed");
+    }
+    for profile in profiles {
+        let last_launched = profile.last_launched.map_or_else(
+            || "never".into(),
+            |date| date.date_naive().to_string(),
+        );
+        println!(
+            "{} - {} on {} {} - {} mods - last launched {last_launched}",
+            profile.name,
+            profile.id,
+            profile.loader,
+            profile.game_version,
+            profile.mod_count,
+        );
+    }
+
+    Ok(())
+}
 
-    let scopes = vec!["XboxLive.signin", "offline_access"];
-    let code = get_device_code(scopes).await?;
-    warn!(
-        "No account was found, please go to {} and enter the code {}",
-        code.verification_uri, code.user_code
+/// Launches the profile `name` installed in `base_path` without updating it,
+/// without using the network at all if `offline`
+async fn launch_installed(
+    args: &Args,
+    name: &str,
+    base_path: &Path,
+    offline: bool,
+) -> Result<()> {
+    let mc_profile = profile::get(base_path)
+        .await?
+        .ok_or(UklientError::NotCached("the profile"))?;
+    let java_installed = mc_profile
+        .java
+        .and_then(|java| java.install)
+        .filter(|path| path.is_file())
+        .is_some();
+    if !java_installed {
+        return Err(UklientError::NotCached("Java"));
+    }
+
+    let cred = if offline && args.login.username.is_none() {
+        let cred = read_credentials(args.login.account.as_deref())?
+            .ok_or(UklientError::NotCached("account"))?;
+        info!("Launching {name} offline as {}", cred.username);
+        cred
+    } else {
+        credentials(args).await?
+    };
+
+    let (max_memory, min_memory, resolution) = (
+        args.profile.max_memory,
+        args.min_memory,
+        args.profile.resolution,
     );
+    if let Some(max_memory) = max_memory {
+        memory::check_max_memory(max_memory, args.profile.strict_memory)?;
+    }
+    if max_memory.is_some() || min_memory.is_some() || resolution.is_some() {
+        profile::edit(base_path, |p| {
+            let mut memory = p.memory.unwrap_or_default();
+            memory.maximum = max_memory.unwrap_or(memory.maximum);
+            memory.minimum = min_memory.or(memory.minimum);
+            p.memory = Some(memory);
+            if resolution.is_some() {
+                p.resolution = resolution;
+            }
+            async { Ok(()) }
+        })
+        .await?;
+    }
+    if let Some(fullscreen) = args.fullscreen {
+        set_fullscreen(base_path, fullscreen)?;
+    }
+
+    launch(args, base_path, &cred).await
+}
 
-    let creds = get_credentials(code.device_code).await?;
-    let file = File::create(credentials_path)?;
-    serde_json::to_writer(BufWriter::new(file), &creds)?;
-
-    Ok(creds)
-}
-
-#[derive(Error, Debug)]
-#[allow(clippy::enum_variant_names)]
-pub enum UklientError {
-    #[error("Java could not be located: {0}")]
-    JavaLocateError(#[from] java_locator::errors::JavaLocatorError),
-    #[error("tokio recv error: {0}")]
-    RecvError(#[from] oneshot::error::RecvError),
-    #[error("io error: {0}")]
-    IoError(#[from] std::io::Error),
-    #[error("fs_extra error: {0}")]
-    FsExtraError(#[from] fs_extra::error::Error),
-    #[error("tokio join error: {0}")]
-    JoinError(#[from] tokio::task::JoinError),
-    #[error("theseus error: {0}")]
-    TheseusError(#[from] theseus::Error),
-    #[error("daedalus error: {0}")]
-    DaedalusError(#[from] daedalus::Error),
-    #[error("json error: {0}")]
-    JsonError(#[from] serde_json::Error),
-    #[error("libium error: {0}")]
-    LibiumError(#[from] libium::upgrade::Error),
-    #[error("libium modpack error: {0}")]
-    LibiumModpackError(#[from] libium::upgrade::modpack_downloadable::Error),
-    #[error("ferinth error: {0}")]
-    FerinthError(#[from] ferinth::Error),
-    #[error("zip error")]
-    ZipError,
-    #[error("{0} version not found")]
-    MetaError(&'static str),
-    #[error("unknown type: {0:?}")]
-    UnknownTypeError(OsString),
-    #[error("tokio acquire error: {0}")]
-    AcquireError(#[from] tokio::sync::AcquireError),
-    #[error("reqwest error: {0}")]
-    ReqwestError(#[from] reqwest::Error),
-    #[error("java not found")]
-    JavaNotFoundError,
-    #[error("minecraft version error: {0}")]
-    VersionError(#[from] crate::version::VersionError),
-    #[error("url parse error: {0}")]
-    UrlParseError(#[from] url::ParseError),
-    #[error("login error: {0}")]
-    LoginError(String),
-}
-
-#[derive(Serialize, Deserialize, Debug, Clone)]
-/// A version of Minecraft that fabric supports
-struct GameVersion {
-    /// The version number of the game
-    pub version: String,
-    /// Whether the Minecraft version is stable or not
-    pub stable: bool,
-}
-
-#[derive(Serialize, Deserialize, Debug, Clone)]
-struct LoaderVersionElement {
-    pub loader: MetaLoaderVersion,
-}
-
-#[derive(Serialize, Deserialize, Debug, Clone)]
-struct MetaLoaderVersion {
-    /// The separator to get the build number
-    pub separator: String,
-    /// The build number
-    pub build: u32,
-    /// The maven artifact
-    pub maven: String,
-    /// The version number of the fabric loader
-    pub version: String,
-    /// Whether the loader is stable or not
-    #[serde(default = "bool::default")]
-    pub stable: bool,
+/// Prints what is useful in a bug report, only saying whether credentials
+/// exist and never reading them
+async fn print_info(
+    args: &Args,
+    game_version: &MinecraftVersion,
+    java_version: u8,
+) -> Result<()> {
+    let wsl = if paths::is_wsl() { " (WSL)" } else { "" };
+    let config = args.config_path.clone().unwrap_or_else(paths::config_path);
+    let config_found = if config.is_file() { "" } else { " (not found)" };
+    let credentials = if paths::credentials_path().is_file() {
+        "saved"
+    } else {
+        "none"
+    };
+    let modpack_id = args
+        .modpack
+        .modpack_id
+        .first()
+        .cloned()
+        .or(State::load()?.last_modpack_id)
+        .unwrap_or_else(|| DEFAULT_MODPACK_ID.into());
+
+    println!("uklient {}", env!("CARGO_PKG_VERSION"));
+    println!("platform: {OS} {ARCH}{wsl}");
+    match memory::total_memory_mb() {
+        Some(total) => println!("memory: {total} MB"),
+        None => println!("memory: unknown"),
+    }
+    println!("data directory: {}", paths::data_dir().display());
+    println!("java directory: {}", paths::java_dir().display());
+    println!("state directory: {}", paths::state_dir().display());
+    println!("config: {}{config_found}", config.display());
+    println!("credentials: {credentials}");
+    println!("modpack: {modpack_id}");
+    println!("game version: {game_version} (Java {java_version})");
+    println!("Java installations:");
+    java::list_java(java_version).await;
+
+    Ok(())
+}
+
+/// The offline account given with --username, or else the Microsoft account
+async fn credentials(args: &Args) -> Result<Credentials> {
+    match &args.login.username {
+        Some(username) => {
+            warn!("Playing offline as {username}, online servers will refuse to connect");
+            Ok(offline_credentials(username))
+        }
+        None => connect_account(&login_options(args)).await,
+    }
+}
+
+fn login_options(args: &Args) -> LoginOptions {
+    LoginOptions {
+        force_relogin: args.login.force_relogin,
+        account: args.login.account.clone(),
+        non_interactive: args.non_interactive,
+    }
+}
+
+/// Fills the settings missing from the command line with the ones of the
+/// config file
+fn apply_config(mut args: Args) -> Result<Args> {
+    let config = match &args.config_path {
+        Some(path) => Config::load(path)?,
+        None => Config::load_default()?,
+    };
+
+    let game_version = match (args.install.game_version, &config.game_version) {
+        (Some(version), _) => version,
+        (None, Some(version)) => MinecraftVersion::parse(version)?,
+        (None, None) => MinecraftVersion::parse(DEFAULT_GAME_VERSION)?,
+    };
+    args.install.game_version = Some(game_version);
+    if args.modpack.modpack_id.is_empty() {
+        args.modpack.modpack_id.extend(config.modpack_id.clone());
+    }
+    args.default_memory_mb = config.default_memory_mb;
+    args.install.loader = args.install.loader.or(config.loader);
+    args.install.default_loader =
+        args.install.default_loader.or(config.default_loader);
+    args.install.modrinth_api_url = args
+        .install
+        .modrinth_api_url
+        .or(config.modrinth_api_url.clone());
+    // the memory and window settings are applied once the modpack is known
+    args.config = config;
+
+    Ok(args)
+}
+
+// what to do instead of installing the modpack and launching it
+#[derive(Debug, Clone, Subcommand)]
+enum Action {
+    /// Install or update the modpack without launching it
+    Install {
+        #[command(flatten)]
+        modpack: ModpackArgs,
+        #[command(flatten)]
+        install: Box<InstallArgs>,
+        #[command(flatten)]
+        profile: ProfileArgs,
+        #[command(flatten)]
+        login: LoginArgs,
+    },
+    /// Launch the installed modpack without updating it
+    Launch {
+        #[command(flatten)]
+        modpack: ModpackArgs,
+        #[command(flatten)]
+        profile: ProfileArgs,
+        #[command(flatten)]
+        login: LoginArgs,
+        #[command(flatten)]
+        game: GameArgs,
+    },
+    /// List the installed profiles
+    List {
+        #[arg(long, help = "print the profiles as json")]
+        json: bool,
+    },
+    /// Delete the installed profile of a modpack
+    Remove {
+        /// The modpack to remove, defaults to the last one installed
+        modpack_id: Option<String>,
+        #[arg(long, help = "remove the profile even if it is locked")]
+        unlock: bool,
+    },
+    /// Manage the installed profiles by name
+    Profiles {
+        #[command(subcommand)]
+        action: ProfilesAction,
+    },
+    /// Manage the saved Microsoft accounts
+    Account {
+        #[command(subcommand)]
+        action: AccountAction,
+    },
+}
+
+#[derive(Debug, Clone, Subcommand)]
+enum AccountAction {
+    /// List the saved accounts, marking the default one
+    List,
+    /// Log in with another account and make it the default one
+    Add,
+    /// Forget a saved account, by username or UUID
+    Remove { account: String },
+    /// Make a saved account the default one, by username or UUID
+    Use { account: String },
+}
+
+#[derive(Debug, Clone, Subcommand)]
+enum ProfilesAction {
+    /// List the registered profiles
+    List {
+        #[arg(long, help = "print the profiles as json")]
+        json: bool,
+    },
+    /// Launch a profile without updating it
+    Launch {
+        name: String,
+        #[command(flatten)]
+        profile: ProfileArgs,
+        #[command(flatten)]
+        login: LoginArgs,
+        #[command(flatten)]
+        game: GameArgs,
+    },
+    /// Give a profile another name
+    Rename { name: String, new_name: String },
+    /// Copy a profile, to change it without touching the original
+    Duplicate { name: String, new_name: String },
+    /// Delete a profile and its directory
+    Delete {
+        name: String,
+        #[arg(long, help = "delete the profile even if it is locked")]
+        unlock: bool,
+    },
+}
+
+/// Which mods --since updates
+#[derive(Debug, Clone, Copy)]
+enum Since {
+    Date(DateTime<Utc>),
+    /// The date of the previous install of the profile
+    LastInstall,
+}
+
+fn parse_since(value: &str) -> std::result::Result<Since, String> {
+    if value == "last-install" {
+        return Ok(Since::LastInstall);
+    }
+
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .ok()
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .map(|date| Since::Date(DateTime::from_utc(date, Utc)))
+        .ok_or_else(|| format!("{value} is not a date like 2023-01-31"))
+}
+
+/// Only accepts the usernames Minecraft accepts
+fn parse_username(value: &str) -> std::result::Result<String, String> {
+    let valid = (3..=16).contains(&value.len())
+        && value.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !valid {
+        return Err("expected 3 to 16 letters, digits or underscores".into());
+    }
+    Ok(value.into())
+}
+
+fn parse_resolution(value: &str) -> std::result::Result<WindowSize, String> {
+    value
+        .split_once('x')
+        .and_then(|(width, height)| {
+            Some(WindowSize(width.parse().ok()?, height.parse().ok()?))
+        })
+        .ok_or_else(|| format!("{value} is not a resolution like 1280x720"))
+}
+
+/// Runs `future`, removing the partial downloads in `dirs` if the user presses
+/// ctrl-c before it completes
+async fn interruptible<T>(
+    future: impl Future<Output = Result<T>>,
+    dirs: &[PathBuf],
+) -> Result<T> {
+    tokio::select! {
+        res = future => res,
+        _ = tokio::signal::ctrl_c() => {
+            warn!("Interrupted, removing partial downloads");
+            for dir in dirs {
+                modpack::remove_partial_files(dir).await?;
+            }
+            Err(UklientError::Interrupted)
+        }
+    }
+}
+
+/// Waits for the game to reach the main menu then kills it, failing if it
+/// exits or takes more than `timeout` seconds before that
+async fn smoke_test(mut process: Child, timeout: u64) -> Result<()> {
+    let stdout = process.stdout.take().ok_or(UklientError::SmokeTestFailed(
+        "the game output is not captured",
+    ))?;
+    let mut lines = BufReader::new(stdout).lines();
+
+    let ready = async {
+        while let Some(line) = lines.next_line().await? {
+            if READY_MARKERS.iter().any(|marker| line.contains(marker)) {
+                return Ok(true);
+            }
+        }
+        Ok::<bool, UklientError>(false)
+    };
+
+    match tokio::time::timeout(Duration::from_secs(timeout), ready).await {
+        Ok(Ok(true)) => {
+            process.kill().await?;
+            info!("Smoke test passed, the game reached the main menu");
+            Ok(())
+        }
+        Ok(Ok(false)) => Err(UklientError::SmokeTestFailed(
+            "the game exited before reaching the main menu",
+        )),
+        Ok(Err(e)) => Err(e),
+        Err(_) => {
+            process.kill().await?;
+            Err(UklientError::SmokeTestFailed(
+                "the game did not reach the main menu in time",
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn since_is_a_date_or_the_last_install() {
+        assert!(matches!(
+            parse_since("last-install"),
+            Ok(Since::LastInstall)
+        ));
+        let Ok(Since::Date(date)) = parse_since("2023-01-31") else {
+            panic!("2023-01-31 is a date");
+        };
+        assert_eq!(date.to_rfc3339(), "2023-01-31T00:00:00+00:00");
+        assert!(parse_since("2023-02-30").is_err());
+        assert!(parse_since("yesterday").is_err());
+    }
+
+    #[test]
+    fn usernames_follow_minecraft_rules() {
+        assert_eq!(parse_username("uku_3lig").unwrap(), "uku_3lig");
+        assert!(parse_username("ab").is_err());
+        assert!(parse_username("seventeen_letters").is_err());
+        assert!(parse_username("no-dashes").is_err());
+        assert!(parse_username("épée").is_err());
+    }
+
+    #[test]
+    fn resolutions_are_width_by_height() {
+        assert_eq!(
+            parse_resolution("1280x720").unwrap(),
+            WindowSize(1280, 720)
+        );
+        assert!(parse_resolution("1280").is_err());
+        assert!(parse_resolution("1280x").is_err());
+        assert!(parse_resolution("-1x720").is_err());
+        assert!(parse_resolution("99999x720").is_err());
+    }
+
+    #[test]
+    fn cli_is_valid() {
+        Args::command().debug_assert();
+    }
+
+    #[test]
+    fn install_flags_belong_to_install() {
+        let args =
+            Args::try_parse_from(["uklient", "install", "--lockfile", "a"])
+                .unwrap();
+        assert!(matches!(
+            args.action,
+            Some(Action::Install { install, .. }) if install.lockfile.is_some()
+        ));
+        assert!(Args::try_parse_from(["uklient", "list", "--lockfile", "a"])
+            .is_err());
+        assert!(Args::try_parse_from([
+            "uklient",
+            "launch",
+            "--since",
+            "2023-01-31"
+        ])
+        .is_err());
+        assert!(Args::try_parse_from([
+            "uklient",
+            "list",
+            "--json",
+            "--no-color"
+        ])
+        .is_ok());
+    }
 }
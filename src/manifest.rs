@@ -0,0 +1,322 @@
+use crate::modpack::modrinth_ids;
+use crate::paths::data_dir;
+use crate::{Result, UklientError};
+use chrono::{DateTime, Utc};
+use libium::modpack::modrinth::structs::ModpackFile;
+use serde::{Deserialize, Serialize};
+use std::fs::read_dir;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use tracing::warn;
+use url::Url;
+
+pub const MANIFEST_FILE: &str = "uklient-manifest.json";
+/// Marks a profile that must not be updated
+pub const LOCK_FILE: &str = "uklient.lock";
+
+/// Holds when a profile was last launched
+pub const LAUNCHED_FILE: &str = "uklient.launched";
+
+pub fn is_locked(profile_dir: &Path) -> bool {
+    profile_dir.join(LOCK_FILE).is_file()
+}
+
+pub fn set_locked(profile_dir: &Path, locked: bool) -> Result<()> {
+    let path = profile_dir.join(LOCK_FILE);
+    if locked {
+        File::create(path)?;
+    } else if path.try_exists()? {
+        std::fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+pub fn mark_launched(profile_dir: &Path) -> Result<()> {
+    let now = Utc::now().to_rfc3339();
+    std::fs::write(profile_dir.join(LAUNCHED_FILE), now)?;
+    Ok(())
+}
+
+pub fn last_launched(profile_dir: &Path) -> Option<DateTime<Utc>> {
+    let launched =
+        std::fs::read_to_string(profile_dir.join(LAUNCHED_FILE)).ok()?;
+    DateTime::parse_from_rfc3339(launched.trim())
+        .ok()
+        .map(|date| date.with_timezone(&Utc))
+}
+
+/// What `list --json` and `profiles list --json` print for each installed
+/// profile
+#[derive(Debug, Clone, Serialize)]
+pub struct ProfileSummary {
+    pub name: String,
+    pub id: String,
+    pub game_version: String,
+    pub loader: String,
+    pub path: PathBuf,
+    pub mod_count: usize,
+    pub last_launched: Option<DateTime<Utc>>,
+}
+
+impl ProfileSummary {
+    pub fn new(profile_dir: &Path, manifest: &Manifest) -> Self {
+        Self {
+            name: profile_dir
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            id: manifest.modpack_id.clone(),
+            game_version: manifest.minecraft_version.clone(),
+            loader: manifest.loader.clone(),
+            path: profile_dir.into(),
+            mod_count: manifest.mods.len(),
+            last_launched: last_launched(profile_dir),
+        }
+    }
+}
+
+/// Describes what was installed in a profile, regenerated on every install
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Manifest {
+    pub modpack_id: String,
+    pub version_id: String,
+    pub minecraft_version: String,
+    pub loader: String,
+    pub loader_version: Option<String>,
+    pub mods: Vec<InstalledMod>,
+    /// When the profile was installed, missing from older manifests
+    #[serde(default)]
+    pub installed_at: Option<DateTime<Utc>>,
+    /// Optional files the user chose not to install
+    #[serde(default)]
+    pub excluded_optional: Vec<String>,
+}
+
+/// What a modpack resolves to, without installing it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResolvedModpack {
+    pub modpack_id: String,
+    pub version_id: String,
+    pub version_name: String,
+    pub minecraft_version: String,
+    pub loader: String,
+    pub loader_version: Option<String>,
+    pub files: Vec<InstalledMod>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InstalledMod {
+    /// Where the file is, relative to the profile directory
+    pub path: PathBuf,
+    pub hashes: Hashes,
+    pub url: Url,
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Hashes {
+    pub sha1: String,
+    pub sha512: String,
+}
+
+impl Manifest {
+    pub fn load(profile_dir: &Path) -> Result<Self> {
+        Self::load_file(&profile_dir.join(MANIFEST_FILE))
+    }
+
+    /// Reads a manifest outside of a profile, e.g. one used as a lockfile
+    pub fn load_file(path: &Path) -> Result<Self> {
+        let file = File::open(path)?;
+        Ok(serde_json::from_reader(BufReader::new(file))?)
+    }
+
+    /// Finds the most recently installed profile of a modpack, returning its
+    /// directory along with its manifest
+    pub fn find(modpack_id: &str) -> Result<(PathBuf, Self)> {
+        Self::list()?
+            .into_iter()
+            .filter(|(_, _, manifest)| manifest.modpack_id == modpack_id)
+            .max_by_key(|(modified, ..)| *modified)
+            .map(|(_, dir, manifest)| (dir, manifest))
+            .ok_or_else(|| UklientError::ManifestNotFound(modpack_id.into()))
+    }
+
+    /// Reads the manifests of all the installed profiles, with the time they
+    /// were written and their profile directory, skipping unreadable ones
+    pub fn list() -> Result<Vec<(SystemTime, PathBuf, Self)>> {
+        let data_dir = data_dir();
+        let mut manifests = Vec::new();
+        if data_dir.is_dir() {
+            for entry in read_dir(data_dir)? {
+                let dir = entry?.path();
+                let path = dir.join(MANIFEST_FILE);
+                if !path.is_file() {
+                    continue;
+                }
+                let modified = path.metadata()?.modified()?;
+                match Self::load(&dir) {
+                    Ok(manifest) => manifests.push((modified, dir, manifest)),
+                    Err(e) => warn!("Skipping the manifest in {dir:?}: {e}"),
+                }
+            }
+        }
+
+        Ok(manifests)
+    }
+
+    pub fn save(&self, profile_dir: &Path) -> Result<()> {
+        let file = File::create(profile_dir.join(MANIFEST_FILE))?;
+        serde_json::to_writer_pretty(BufWriter::new(file), self)?;
+        Ok(())
+    }
+}
+
+impl InstalledMod {
+    pub fn filename(&self) -> String {
+        self.path
+            .file_name()
+            .map_or_else(String::new, |n| n.to_string_lossy().into())
+    }
+
+    /// Identifies the mod across versions, by project id if it comes from
+    /// Modrinth or by filename otherwise
+    fn key(&self) -> String {
+        modrinth_ids(&self.url)
+            .map_or_else(|| self.filename(), |(project, _)| project.into())
+    }
+}
+
+/// How the mods of two manifests differ
+#[derive(Debug, Default)]
+pub struct ModsDiff<'a> {
+    pub only_a: Vec<&'a InstalledMod>,
+    pub only_b: Vec<&'a InstalledMod>,
+    pub changed: Vec<(&'a InstalledMod, &'a InstalledMod)>,
+}
+
+pub fn diff<'a>(a: &'a Manifest, b: &'a Manifest) -> ModsDiff<'a> {
+    let mut diff = ModsDiff::default();
+    for mod_a in &a.mods {
+        match b.mods.iter().find(|mod_b| mod_b.key() == mod_a.key()) {
+            Some(mod_b) if mod_b.hashes != mod_a.hashes => {
+                diff.changed.push((mod_a, mod_b));
+            }
+            Some(_) => {}
+            None => diff.only_a.push(mod_a),
+        }
+    }
+    for mod_b in &b.mods {
+        if !a.mods.iter().any(|mod_a| mod_a.key() == mod_b.key()) {
+            diff.only_b.push(mod_b);
+        }
+    }
+
+    diff
+}
+
+impl TryFrom<&ModpackFile> for InstalledMod {
+    type Error = crate::UklientError;
+
+    fn try_from(file: &ModpackFile) -> Result<Self> {
+        // libium doesn't make the hashes public, go through their json form
+        let hashes =
+            serde_json::from_value(serde_json::to_value(&file.hashes)?)?;
+
+        Ok(Self {
+            path: file.path.clone(),
+            hashes,
+            url: file
+                .downloads
+                .first()
+                .ok_or_else(|| {
+                    UklientError::MissingDownload(file.path.clone())
+                })?
+                .clone(),
+            size: file.file_size,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn installed(filename: &str, project: &str, sha1: &str) -> InstalledMod {
+        InstalledMod {
+            path: PathBuf::from("mods").join(filename),
+            hashes: Hashes {
+                sha1: sha1.into(),
+                sha512: String::new(),
+            },
+            url: Url::parse(&format!(
+                "https://cdn.modrinth.com/data/{project}/versions/abcdefgh/{filename}"
+            ))
+            .unwrap(),
+            size: 0,
+        }
+    }
+
+    fn manifest(mods: Vec<InstalledMod>) -> Manifest {
+        Manifest {
+            modpack_id: "pack".into(),
+            version_id: "version".into(),
+            minecraft_version: "1.19.3".into(),
+            loader: "fabric".into(),
+            loader_version: None,
+            mods,
+            installed_at: None,
+            excluded_optional: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn diff_finds_added_removed_and_updated_mods() {
+        let a = manifest(vec![
+            installed("sodium-0.4.9.jar", "AANobbMI", "1"),
+            installed("lithium-0.11.jar", "gvQqBUqZ", "2"),
+            installed("iris-1.5.jar", "YL57xq9U", "3"),
+        ]);
+        let b = manifest(vec![
+            installed("sodium-0.4.10.jar", "AANobbMI", "4"),
+            installed("iris-1.5.jar", "YL57xq9U", "3"),
+            installed("indium-1.0.jar", "Orvt0mRa", "5"),
+        ]);
+
+        let diff = diff(&a, &b);
+        let names = |mods: &[&InstalledMod]| {
+            mods.iter().map(|m| m.filename()).collect::<Vec<_>>()
+        };
+        assert_eq!(names(&diff.only_a), ["lithium-0.11.jar"]);
+        assert_eq!(names(&diff.only_b), ["indium-1.0.jar"]);
+        assert_eq!(diff.changed.len(), 1);
+        assert_eq!(diff.changed[0].0.filename(), "sodium-0.4.9.jar");
+        assert_eq!(diff.changed[0].1.filename(), "sodium-0.4.10.jar");
+    }
+
+    #[test]
+    fn identical_manifests_have_no_diff() {
+        let a = manifest(vec![installed("sodium-0.4.9.jar", "AANobbMI", "1")]);
+        let diff = diff(&a, &a);
+        assert!(diff.only_a.is_empty());
+        assert!(diff.only_b.is_empty());
+        assert!(diff.changed.is_empty());
+    }
+
+    #[test]
+    fn files_without_downloads_are_rejected() {
+        let file: ModpackFile = serde_json::from_value(serde_json::json!({
+            "path": "mods/sodium.jar",
+            "hashes": { "sha1": "", "sha512": "" },
+            "downloads": [],
+            "fileSize": 0,
+        }))
+        .unwrap();
+
+        assert!(matches!(
+            InstalledMod::try_from(&file),
+            Err(UklientError::MissingDownload(path)) if path == Path::new("mods/sodium.jar")
+        ));
+    }
+}
@@ -0,0 +1,30 @@
+use crate::{Result, UklientError};
+use sysinfo::{RefreshKind, System, SystemExt};
+use tracing::warn;
+
+/// The RAM of the machine in MB, if it can be detected
+pub fn total_memory_mb() -> Option<u64> {
+    let system = System::new_with_specifics(RefreshKind::new().with_memory());
+    let total = system.total_memory() / (1024 * 1024);
+    (total > 0).then_some(total)
+}
+
+/// Warns when the game is given more memory than the machine has, which makes
+/// the JVM fail to start with a confusing error. Fails instead with `strict`
+pub fn check_max_memory(max_memory: u32, strict: bool) -> Result<()> {
+    let Some(total) = total_memory_mb() else {
+        return Ok(());
+    };
+    if u64::from(max_memory) <= total {
+        return Ok(());
+    }
+
+    if strict {
+        return Err(UklientError::NotEnoughMemory {
+            requested: max_memory,
+            total,
+        });
+    }
+    warn!("The game is given {max_memory} MB of memory but this machine only has {total} MB");
+    Ok(())
+}
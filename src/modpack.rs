@@ -1,155 +1,1170 @@
-use crate::UklientError::{MetaError, UnknownTypeError, ZipError};
-use crate::{
-    get_latest_fabric, get_latest_quilt, Result, UklientError, CLIENT,
+use crate::ipc::{self, Event};
+use crate::loader::{
+    get_latest_fabric, get_latest_forge, get_latest_legacy_fabric,
+    get_latest_neoforge, get_latest_quilt, Loader, FABRIC_META_URL,
+    LEGACY_FABRIC_META_URL, QUILT_META_URL,
 };
+use crate::manifest::{InstalledMod, Manifest, ResolvedModpack, MANIFEST_FILE};
+use crate::modrinth::Modrinth;
+use crate::paths::{cache_dir, denylist_path, patches_dir, tmp_dir};
+use crate::UklientError::{MetaError, UnknownTypeError, ZipError};
+use crate::{http, Result, UklientError, STYLE_COUNT};
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
 use daedalus::modded::LoaderVersion;
-use ferinth::Ferinth;
+use dialoguer::MultiSelect;
+use ferinth::structures::project::ProjectSupportRange;
+use ferinth::structures::version::{DependencyType, Version, VersionType};
 use fs_extra::{
     dir::{copy as copy_dir, CopyOptions as DirCopyOptions},
     file::{move_file, CopyOptions as FileCopyOptions},
 };
+use indicatif::ProgressBar;
 use itertools::Itertools;
 use libium::modpack::extract_zip;
-use libium::modpack::modrinth::deser_metadata;
 use libium::modpack::modrinth::read_metadata_file;
+use libium::modpack::modrinth::structs::ModpackFile;
 use libium::upgrade::Downloadable;
 use libium::version_ext::VersionExt;
-use libium::HOME;
+use reqwest::StatusCode;
+use serde::Deserialize;
 use std::fs::File;
 use std::{
+    collections::{HashMap, HashSet},
     ffi::OsString,
     fs::read_dir,
+    num::NonZeroUsize,
     path::{Path, PathBuf},
     sync::Arc,
+    thread::available_parallelism,
 };
 use theseus::prelude::{ModLoader, ProfileMetadata};
 use tokio::{
-    fs::{copy, create_dir_all, remove_file},
+    fs::{copy, create_dir_all, hard_link, remove_dir_all, remove_file},
     sync::Semaphore,
-    task::JoinSet,
+    task::{spawn_blocking, JoinSet},
 };
-use tracing::{info, warn};
+use tracing::{debug, debug_span, info, warn, Instrument};
+use url::Url;
 
+/// Resolves the theseus profile of a modpack, along with the loader it is
+/// installed with
 pub async fn get_metadata(
+    modrinth: &Modrinth,
     id: &str,
     game_version: &str,
-) -> Result<ProfileMetadata> {
-    let modrinth = Ferinth::default();
-
+    options: &InstallOptions,
+) -> Result<(ProfileMetadata, Loader)> {
     let info = modrinth.get_project(id).await?;
-    let versions = modrinth
-        .list_versions_filtered(id, None, Some(&[game_version]), None)
-        .await?;
+    let meta = find_version(
+        modrinth,
+        id,
+        game_version,
+        options.pack_version.as_deref(),
+        options.channel,
+    )
+    .await?;
 
-    if let Some(meta) = versions.first() {
-        let loader_info = match meta.loaders.first() {
-            Some(l) => LoaderInfo::from(l, &game_version.into()).await?,
-            None => return Err(MetaError("loader")),
-        };
+    let loader = pack_loader(options, &meta.loaders).unwrap_or_else(|| {
+        info!(
+            "The modpack doesn't specify a loader, using {:?}",
+            options.default_loader
+        );
+        options.default_loader
+    });
+    let reused = options.installed_loader.as_ref().and_then(
+        |(installed_loader, installed)| {
+            LoaderInfo::reuse(
+                *installed_loader,
+                installed,
+                loader,
+                game_version,
+            )
+        },
+    );
+    let loader_info = match reused {
+        Some(info) => {
+            info!("Keeping the installed {:?} {}", info.kind, info.version.id);
+            info
+        }
+        None => {
+            LoaderInfo::from(loader, game_version, options)
+                .instrument(debug_span!("loader resolution"))
+                .await?
+        }
+    };
 
-        Ok(ProfileMetadata {
-            name: format!("{}-{}", info.title, meta.name),
-            loader: loader_info.loader,
-            loader_version: Some(loader_info.version),
-            game_version: game_version.into(),
-            format_version: 1,
-            icon: None,
-        })
-    } else {
-        Err(MetaError("modpack"))
+    let metadata = ProfileMetadata {
+        name: format!("{}-{}", info.title, meta.name),
+        loader: loader_info.loader,
+        loader_version: Some(loader_info.version),
+        game_version: game_version.into(),
+        format_version: 1,
+        icon: None,
+    };
+    Ok((metadata, loader_info.kind))
+}
+
+/// The loader given on the command line, or else the one of the modpack
+fn pack_loader(options: &InstallOptions, loaders: &[String]) -> Option<Loader> {
+    options
+        .loader
+        .or_else(|| loaders.iter().find_map(|l| Loader::from_modrinth(l)))
+}
+
+/// Checks that the modpack exists, suggesting similarly named ones otherwise
+pub async fn validate_modpack_id(modrinth: &Modrinth, id: &str) -> Result<()> {
+    match modrinth.get_project(id).await {
+        Ok(_) => return Ok(()),
+        Err(UklientError::ReqwestError(e))
+            if e.status() == Some(StatusCode::NOT_FOUND) => {}
+        Err(e) => return Err(e),
+    }
+
+    match modrinth.search_modpacks(id).await {
+        Ok(hits) if !hits.is_empty() => warn!(
+            "Modpack {id} does not exist, did you mean {}?",
+            hits.iter()
+                .map(|hit| format!("{} ({})", hit.slug, hit.title))
+                .format(", ")
+        ),
+        Ok(_) => {}
+        Err(e) => debug!("Could not search for similar modpacks: {e}"),
+    }
+
+    Err(UklientError::ModpackNotFound(id.into()))
+}
+
+/// The versions of a modpack that can be installed on `game_version`
+pub async fn list_versions(
+    modrinth: &Modrinth,
+    id: &str,
+    game_version: &str,
+) -> Result<Vec<Version>> {
+    modrinth.list_versions(id, Some(game_version)).await
+}
+
+/// The least stable kind of modpack versions that can be installed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum Channel {
+    #[default]
+    Release,
+    Beta,
+    Alpha,
+}
+
+impl Channel {
+    fn allows(self, version_type: &VersionType) -> bool {
+        match version_type {
+            VersionType::Release => true,
+            VersionType::Beta => self != Channel::Release,
+            VersionType::Alpha => self == Channel::Alpha,
+        }
+    }
+}
+
+/// Finds the modpack version matching `pack_version` (by id, name or version
+/// number), or the latest one from `channel` if none is specified
+async fn find_version(
+    modrinth: &Modrinth,
+    id: &str,
+    game_version: &str,
+    pack_version: Option<&str>,
+    channel: Channel,
+) -> Result<Version> {
+    let versions = modrinth.list_versions(id, Some(game_version)).await?;
+
+    match pack_version {
+        Some(wanted) => versions.into_iter().find(|v| {
+            v.id == wanted || v.name == wanted || v.version_number == wanted
+        }),
+        None => versions
+            .into_iter()
+            .find(|v| channel.allows(&v.version_type)),
     }
+    .ok_or(MetaError("modpack"))
 }
 
 #[derive(Debug)]
 struct LoaderInfo {
     loader: ModLoader,
+    /// The loader as uklient knows it, theseus launching some of them the
+    /// same way
+    kind: Loader,
     version: LoaderVersion,
 }
 
 impl LoaderInfo {
-    async fn from(value: &str, game_version: &String) -> Result<Self> {
-        match value.to_ascii_lowercase().as_str() {
-            "fabric" => Ok(Self {
-                loader: ModLoader::Fabric,
-                version: get_latest_fabric(game_version).await?,
-            }),
-            "quilt" => Ok(Self {
-                loader: ModLoader::Quilt,
-                version: get_latest_quilt(game_version).await?,
-            }),
-            _ => Err(MetaError("unknown loader")),
+    async fn from(
+        loader: Loader,
+        game_version: &str,
+        options: &InstallOptions,
+    ) -> Result<Self> {
+        let stable = options.prefer_stable;
+        let version = match loader {
+            Loader::Fabric => {
+                get_latest_fabric(FABRIC_META_URL, game_version, stable).await?
+            }
+            Loader::LegacyFabric => {
+                get_latest_legacy_fabric(
+                    LEGACY_FABRIC_META_URL,
+                    game_version,
+                    stable,
+                )
+                .await?
+            }
+            Loader::Quilt => {
+                match get_latest_quilt(QUILT_META_URL, game_version, stable)
+                    .await
+                {
+                    Ok(version) => version,
+                    // quilt runs most fabric mods, and fabric is often quicker to
+                    // support new minecraft versions
+                    Err(MetaError(_)) if options.allow_fabric_fallback => {
+                        warn!(
+                        "No Quilt loader found for Minecraft {game_version}, using Fabric instead"
+                    );
+                        return Ok(Self {
+                            loader: ModLoader::Fabric,
+                            kind: Loader::Fabric,
+                            version: get_latest_fabric(
+                                FABRIC_META_URL,
+                                game_version,
+                                stable,
+                            )
+                            .await?,
+                        });
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+            Loader::Forge => get_latest_forge(game_version, stable).await?,
+            Loader::NeoForge => {
+                get_latest_neoforge(game_version, stable).await?
+            }
+        };
+
+        Ok(Self {
+            loader: mod_loader(loader),
+            kind: loader,
+            version,
+        })
+    }
+
+    /// The loader of an installed profile, if it is the one that would be
+    /// installed for `loader` and `game_version`. `installed_loader` comes
+    /// from the manifest of the profile, theseus launching NeoForge as Forge.
+    fn reuse(
+        installed_loader: Loader,
+        installed: &ProfileMetadata,
+        loader: Loader,
+        game_version: &str,
+    ) -> Option<Self> {
+        let Some(version) = &installed.loader_version else {
+            warn!("The profile has no loader installed, resolving it");
+            return None;
+        };
+        if installed_loader != loader
+            || installed.loader != mod_loader(loader)
+            || installed.game_version != game_version
+        {
+            warn!(
+                "The installed loader is {installed_loader:?} for Minecraft {}, not {loader:?} for Minecraft {game_version}, resolving it again",
+                installed.game_version
+            );
+            return None;
         }
+
+        Some(Self {
+            loader: installed.loader,
+            kind: loader,
+            version: version.clone(),
+        })
     }
 }
 
+/// How theseus launches `loader`
+fn mod_loader(loader: Loader) -> ModLoader {
+    match loader {
+        // legacy fabric is launched like regular fabric
+        Loader::Fabric | Loader::LegacyFabric => ModLoader::Fabric,
+        Loader::Quilt => ModLoader::Quilt,
+        // theseus has no NeoForge loader, but launches it like forge
+        Loader::Forge | Loader::NeoForge => ModLoader::Forge,
+    }
+}
+
+/// Settings changing what gets installed from a modpack
+#[derive(Debug, Clone, Default)]
+pub struct InstallOptions {
+    /// The modpack version to install instead of the latest one
+    pub pack_version: Option<String>,
+    /// Which versions are considered when looking for the latest one
+    pub channel: Channel,
+    /// The loader to install instead of the one of the modpack
+    pub loader: Option<Loader>,
+    /// The loader used when the modpack doesn't specify one
+    pub default_loader: Loader,
+    /// Whether Fabric is installed when a Quilt loader can't be found
+    pub allow_fabric_fallback: bool,
+    /// Whether a stable loader is installed over a newer prerelease
+    pub prefer_stable: bool,
+    /// Whether overrides replace files already present in the profile
+    pub force_overrides: bool,
+    /// Mods that won't be installed, by filename or project id
+    pub skip_mods: Vec<String>,
+    /// Mods that are never installed in any profile, by filename or project id
+    pub denylist: Vec<String>,
+    /// If not empty, the only mods that will be installed along with their
+    /// dependencies, by filename or project id
+    pub only_mods: Vec<String>,
+    /// Whether the files the modpack marks as optional are all left out
+    pub no_optional: bool,
+    /// Whether the user is asked which optional files to install
+    pub choose_optional: bool,
+    /// Optional files left out by a previous install, by filename
+    pub excluded_optional: Vec<String>,
+    /// Whether mods that don't support the loader stop the install
+    pub strict_compat: bool,
+    /// If set, mods keep their previous version unless the new one was
+    /// published after this date
+    pub since: Option<DateTime<Utc>>,
+    /// The mods installed in the profile before this install
+    pub previous_mods: Vec<InstalledMod>,
+    /// The exact mods to install instead of the ones of the index, from a
+    /// lockfile
+    pub pinned: Option<Vec<InstalledMod>>,
+    /// Whether the mods the modpack dropped since the previous install are
+    /// deleted instead of kept in `.old`
+    pub prune: bool,
+    /// Whether identical files are shared between profiles instead of being
+    /// downloaded in each one
+    pub dedupe: bool,
+    /// The profile whose loader is kept instead of installing the latest one,
+    /// with --skip-loader-install, and the loader its manifest records
+    pub installed_loader: Option<(Loader, ProfileMetadata)>,
+    /// Whether mods that fail to download are reported at the end instead of
+    /// stopping the install
+    pub best_effort: bool,
+    /// Whether files that don't support the client are installed anyway
+    pub include_server_mods: bool,
+    /// Where mods are installed instead of the `mods` folder of the profile
+    pub mods_dir: Option<PathBuf>,
+}
+
+/// The result of a successful install
+#[derive(Debug, Clone)]
+pub struct Installed {
+    pub version_id: String,
+    pub mods: Vec<InstalledMod>,
+    /// The optional files that weren't installed
+    pub excluded_optional: Vec<String>,
+    /// The files that couldn't be downloaded, with `best_effort`
+    pub failed: Vec<String>,
+}
+
 // code BLATANTLY stolen from ferium
 
 pub async fn install_modpack(
+    modrinth: &Modrinth,
     output_dir: &Path,
     id: &str,
     game_version: String,
-) -> Result<()> {
-    let modrinth = Ferinth::default();
+    options: &InstallOptions,
+) -> Result<Installed> {
+    let force_overrides = options.force_overrides;
 
-    let version = modrinth
-        .list_versions(id)
-        .await?
-        .iter()
-        .find(|v| v.game_versions.contains(&game_version))
-        .ok_or(MetaError("modpack"))?
-        .clone();
+    let version = find_version(
+        modrinth,
+        id,
+        &game_version,
+        options.pack_version.as_deref(),
+        options.channel,
+    )
+    .await?;
 
     info!("Found modpack version {}", version.name);
+    let version_id = version.id.clone();
+    let loaders = version.loaders.clone();
 
-    let mut version_file: Downloadable = version.into_version_file().into();
-    version_file.output = version_file.filename().into();
-
-    let cache_dir = HOME.join(".config").join("uklient").join(".cache");
-    create_dir_all(&cache_dir).await?;
+    let modpack_file = File::open(fetch_modpack(version).await?)?;
+    let metadata = read_index(&modpack_file)?;
 
-    let modpack_path = cache_dir.join(&version_file.output);
-    if !modpack_path.exists() {
-        version_file.download(&CLIENT, &cache_dir, |_| {}).await?;
-    }
-
-    let modpack_file = File::open(modpack_path)?;
-    let metadata = deser_metadata(
-        &read_metadata_file(&modpack_file).map_err(|_| ZipError)?,
-    )?;
-
-    let tmp_dir = HOME
-        .join(".config")
-        .join("uklient")
-        .join(".tmp")
-        .join(metadata.name);
+    let tmp_dir = tmp_dir().join(metadata.name);
     extract_zip(modpack_file, &tmp_dir)
         .await
         .map_err(|_| ZipError)?;
-    let overrides = read_overrides(&tmp_dir.join("overrides"))?;
+    let mut overrides = read_overrides(&tmp_dir.join("overrides"))?;
+    overrides.extend(read_overrides(&tmp_dir.join("client-overrides"))?);
+    // existing files are skipped when not forcing, so client-overrides have
+    // to be copied first to take precedence over the common overrides
+    if !force_overrides {
+        overrides.reverse();
+    }
 
-    let mut to_download: Vec<Downloadable> = Vec::new();
+    let mods_dir = options
+        .mods_dir
+        .clone()
+        .unwrap_or_else(|| output_dir.join("mods"));
+    if !mods_dir.starts_with(output_dir) {
+        warn!("Installing mods outside of the profile, in {mods_dir:?}");
+    }
+    let mut files = Vec::new();
+    let mut optional = Vec::new();
     for file in metadata.files {
+        let client = FileEnvironment::of(&file)?.map(|env| env.client);
+        match client {
+            Some(ProjectSupportRange::Unsupported)
+                if !options.include_server_mods =>
+            {
+                info!("Skipping server-only {}", file.path.display());
+            }
+            Some(ProjectSupportRange::Optional) => optional.push(file),
+            _ => files.push(file),
+        }
+    }
+    let excluded_optional = exclude_optional(&mut optional, options)?;
+    files.append(&mut optional);
+    for file in &mut files {
+        // absolute paths replace the output directory when joined to it
+        if options.mods_dir.is_some() && file.path.starts_with("mods") {
+            if let Some(filename) = file.path.file_name() {
+                file.path = mods_dir.join(filename);
+            }
+        }
+    }
+
+    let mut to_download: Vec<Downloadable> = Vec::new();
+    for file in files.iter().cloned() {
         to_download.push(file.into());
     }
+    if !options.only_mods.is_empty() {
+        to_download =
+            keep_only(modrinth, to_download, &options.only_mods).await?;
+    }
+    to_download.retain(|file| {
+        let skipped = options.skip_mods.iter().any(|m| matches_mod(file, m));
+        if skipped {
+            info!("Skipping {}", file.filename());
+        }
+        !skipped
+    });
+    to_download.retain(|file| {
+        let denied = options.denylist.iter().any(|m| matches_mod(file, m));
+        if denied {
+            info!("Skipping {}, it is in the denylist", file.filename());
+        }
+        !denied
+    });
+    let loader =
+        pack_loader(options, &loaders).unwrap_or(options.default_loader);
+    let mods = to_download
+        .iter()
+        .filter(|file| output_dir.join(&file.output).starts_with(&mods_dir))
+        .collect_vec();
+    check_compat(modrinth, &mods, loader, options.strict_compat).await?;
+    if let Some(since) = options.since {
+        to_download =
+            hold_back(modrinth, to_download, since, &options.previous_mods)
+                .await?;
+    }
+    if let Some(pinned) = &options.pinned {
+        to_download = pin(to_download, pinned);
+    }
+    let installed_urls = to_download
+        .iter()
+        .map(|file| &file.download_url)
+        .collect::<HashSet<_>>();
+    let mut mods = files
+        .iter()
+        .filter(|file| {
+            file.downloads
+                .first()
+                .map_or(false, |url| installed_urls.contains(url))
+        })
+        .map(InstalledMod::try_from)
+        .collect::<Result<Vec<_>>>()?;
+    // mods held back by --since or pinned by a lockfile aren't in the index
+    mods.extend(
+        options
+            .previous_mods
+            .iter()
+            .chain(options.pinned.iter().flatten())
+            .filter(|m| installed_urls.contains(&m.url))
+            .filter(|m| {
+                !files.iter().any(|f| f.downloads.first() == Some(&m.url))
+            })
+            .unique_by(|m| &m.url)
+            .cloned()
+            .collect_vec(),
+    );
 
-    clean(&output_dir.join("mods"), &mut to_download, &mut Vec::new()).await?;
+    // a mods folder outside of the profile can be shared, only the files of
+    // the previous install are uklient's to clean up
+    let owned = (!mods_dir.starts_with(output_dir)).then(|| {
+        options
+            .previous_mods
+            .iter()
+            .map(|m| output_dir.join(&m.path))
+            .collect::<HashSet<_>>()
+    });
+    clean(&mods_dir, &mut to_download, &mut Vec::new(), owned.as_ref()).await?;
     clean(
         &output_dir.join("resourcepacks"),
         &mut to_download,
         &mut Vec::new(),
+        None,
     )
     .await?;
+    if options.prune {
+        prune(output_dir, &options.previous_mods, &mods).await?;
+    }
 
+    let mut failed = Vec::new();
     if to_download.is_empty() && overrides.is_empty() {
         info!("Everything is up to date!");
-        Ok(())
     } else {
-        download(output_dir.into(), to_download, overrides).await
+        let store = options.dedupe.then(|| {
+            mods.iter()
+                .map(|m| (m.url.clone(), m.hashes.sha1.clone()))
+                .collect()
+        });
+        // the index can list several urls for the same file
+        let mirrors = files
+            .iter()
+            .filter_map(|f| f.downloads.split_first())
+            .map(|(url, mirrors)| (url.clone(), mirrors.to_vec()))
+            .collect();
+        failed = download(
+            output_dir.into(),
+            to_download,
+            overrides,
+            force_overrides,
+            store,
+            mirrors,
+            options.best_effort,
+        )
+        .await?;
+    }
+
+    Ok(Installed {
+        version_id,
+        mods,
+        excluded_optional,
+        failed,
+    })
+}
+
+/// Replaces the files of the index with the versions pinned by a lockfile,
+/// warning about the ones that differ
+fn pin(files: Vec<Downloadable>, pinned: &[InstalledMod]) -> Vec<Downloadable> {
+    let same_mod = |file: &Downloadable, m: &InstalledMod| match (
+        modrinth_ids(&file.download_url),
+        modrinth_ids(&m.url),
+    ) {
+        (Some((a, _)), Some((b, _))) => a == b,
+        _ => file.filename() == m.filename(),
+    };
+
+    for m in pinned {
+        if !files.iter().any(|file| same_mod(file, m)) {
+            warn!("{} is pinned but not part of the modpack", m.filename());
+        }
+    }
+
+    files
+        .into_iter()
+        .map(|file| match pinned.iter().find(|m| same_mod(&file, m)) {
+            Some(m) if m.url != file.download_url => {
+                warn!(
+                    "Installing the pinned {} instead of {}",
+                    m.filename(),
+                    file.filename()
+                );
+                Downloadable {
+                    download_url: m.url.clone(),
+                    output: m.path.clone(),
+                    length: m.size,
+                }
+            }
+            Some(_) => file,
+            None => {
+                warn!("{} is not pinned by the lockfile", file.filename());
+                file
+            }
+        })
+        .collect()
+}
+
+/// Replaces the mods whose new version was published before `since` with
+/// the version that was previously installed
+async fn hold_back(
+    modrinth: &Modrinth,
+    files: Vec<Downloadable>,
+    since: DateTime<Utc>,
+    previous: &[InstalledMod],
+) -> Result<Vec<Downloadable>> {
+    let previous_version = |file: &Downloadable| {
+        let (project, _) = modrinth_ids(&file.download_url)?;
+        previous.iter().find(|m| {
+            m.url != file.download_url
+                && modrinth_ids(&m.url).map(|(p, _)| p) == Some(project)
+        })
+    };
+
+    let version_ids = files
+        .iter()
+        .filter(|f| previous_version(f).is_some())
+        .filter_map(|f| modrinth_ids(&f.download_url))
+        .map(|(_, version)| version)
+        .collect_vec();
+    if version_ids.is_empty() {
+        return Ok(files);
+    }
+    let published = modrinth
+        .get_multiple_versions(&version_ids)
+        .await?
+        .into_iter()
+        .map(|v| (v.id, v.date_published))
+        .collect::<HashMap<_, _>>();
+
+    Ok(files
+        .into_iter()
+        .map(|file| {
+            let date = modrinth_ids(&file.download_url)
+                .and_then(|(_, version)| published.get(version));
+            match (date, previous_version(&file)) {
+                (Some(date), Some(old)) if *date <= since => {
+                    info!(
+                        "Keeping {}, {} was published before {}",
+                        old.filename(),
+                        file.filename(),
+                        since.date_naive()
+                    );
+                    Downloadable {
+                        download_url: old.url.clone(),
+                        output: old.path.clone(),
+                        length: old.size,
+                    }
+                }
+                _ => file,
+            }
+        })
+        .collect())
+}
+
+/// Warns about the mods that don't declare support for `loader`, failing
+/// instead when `strict`
+async fn check_compat(
+    modrinth: &Modrinth,
+    mods: &[&Downloadable],
+    loader: Loader,
+    strict: bool,
+) -> Result<()> {
+    let version_ids = mods
+        .iter()
+        .filter_map(|f| modrinth_ids(&f.download_url))
+        .map(|(_, version)| version)
+        .collect_vec();
+    if version_ids.is_empty() {
+        return Ok(());
+    }
+
+    let incompatible = modrinth
+        .get_multiple_versions(&version_ids)
+        .await?
+        .into_iter()
+        .filter(|v| !v.loaders.iter().any(|l| loader.runs(l)))
+        .collect_vec();
+    for version in &incompatible {
+        let name = version.files.first().map_or(&version.name, |f| &f.filename);
+        warn!(
+            "{name} only supports {}, not {loader:?}",
+            version.loaders.join(", ")
+        );
+    }
+
+    if strict && !incompatible.is_empty() {
+        return Err(UklientError::IncompatibleMods(incompatible.len()));
+    }
+    Ok(())
+}
+
+/// Deletes the mods of the previous install that are no longer part of the
+/// modpack, which `clean` only moves to `.old`
+async fn prune(
+    output_dir: &Path,
+    previous: &[InstalledMod],
+    current: &[InstalledMod],
+) -> Result<()> {
+    let dropped = previous
+        .iter()
+        .filter(|old| !current.iter().any(|m| m.filename() == old.filename()));
+    for old in dropped {
+        let path = output_dir.join(&old.path);
+        let moved = path
+            .parent()
+            .map(|dir| dir.join(".old").join(old.filename()));
+        for path in std::iter::once(path).chain(moved) {
+            if path.is_file() {
+                info!(
+                    "Deleting {}, it was removed from the modpack",
+                    old.filename()
+                );
+                remove_file(path).await?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Removes the optional files the user doesn't want from `optional`,
+/// returning their filenames
+fn exclude_optional(
+    optional: &mut Vec<ModpackFile>,
+    options: &InstallOptions,
+) -> Result<Vec<String>> {
+    let names = optional
+        .iter()
+        .map(|file| file.path.file_name().unwrap_or_default())
+        .map(|name| name.to_string_lossy().to_string())
+        .collect::<Vec<_>>();
+
+    let keep = if options.no_optional {
+        vec![false; names.len()]
+    } else if options.choose_optional && !names.is_empty() {
+        let defaults = names
+            .iter()
+            .map(|name| !options.excluded_optional.contains(name))
+            .collect::<Vec<_>>();
+        let chosen = MultiSelect::new()
+            .with_prompt("Choose the optional files to install")
+            .items(&names)
+            .defaults(&defaults)
+            .interact()?;
+        (0..names.len()).map(|i| chosen.contains(&i)).collect()
+    } else {
+        names
+            .iter()
+            .map(|name| !options.excluded_optional.contains(name))
+            .collect()
+    };
+
+    let excluded = names
+        .into_iter()
+        .zip(&keep)
+        .filter(|(_, kept)| !**kept)
+        .map(|(name, _)| name)
+        .collect();
+    let mut keep = keep.into_iter();
+    optional.retain(|file| {
+        let kept = keep.next().unwrap_or(true);
+        if !kept {
+            info!("Skipping optional {}", file.path.display());
+        }
+        kept
+    });
+
+    Ok(excluded)
+}
+
+/// Downloads the `.mrpack` of a modpack version, unless it is already cached
+async fn fetch_modpack(version: Version) -> Result<PathBuf> {
+    let mut version_file: Downloadable = version.into_version_file().into();
+    version_file.output = version_file.filename().into();
+
+    let cache_dir = cache_dir();
+    create_dir_all(&cache_dir).await?;
+
+    let modpack_path = cache_dir.join(&version_file.output);
+    if !modpack_path.exists() {
+        http::download(&version_file, &cache_dir).await?;
+    }
+
+    Ok(modpack_path)
+}
+
+/// The parts of a `modrinth.index.json` uklient uses. libium's version fails
+/// on the loaders it doesn't know in `dependencies`, like `neoforge`
+#[derive(Debug, Deserialize)]
+struct Index {
+    name: String,
+    files: Vec<ModpackFile>,
+}
+
+/// Parses the `modrinth.index.json` of a modpack, saying which field is wrong
+/// when it is malformed
+fn read_index(modpack_file: &File) -> Result<Index> {
+    let index = read_metadata_file(modpack_file).map_err(|_| ZipError)?;
+    parse_index(&index)
+}
+
+fn parse_index(index: &str) -> Result<Index> {
+    let deserializer = &mut serde_json::Deserializer::from_str(index);
+
+    let index: Index =
+        serde_path_to_error::deserialize(deserializer).map_err(|e| {
+            UklientError::InvalidIndex {
+                path: e.path().to_string(),
+                message: e.into_inner().to_string(),
+            }
+        })?;
+    // every file is downloaded from its first url
+    if let Some(i) = index.files.iter().position(|f| f.downloads.is_empty()) {
+        return Err(UklientError::InvalidIndex {
+            path: format!("files[{i}].downloads"),
+            message: "no download url".into(),
+        });
+    }
+    Ok(index)
+}
+
+/// Resolves the version, loader and files of a modpack like an install would
+pub async fn resolve(
+    modrinth: &Modrinth,
+    id: &str,
+    game_version: &str,
+    options: &InstallOptions,
+) -> Result<ResolvedModpack> {
+    let (profile, loader) =
+        get_metadata(modrinth, id, game_version, options).await?;
+    let version = find_version(
+        modrinth,
+        id,
+        game_version,
+        options.pack_version.as_deref(),
+        options.channel,
+    )
+    .await?;
+    let version_id = version.id.clone();
+    let version_name = version.name.clone();
+
+    let metadata = read_index(&File::open(fetch_modpack(version).await?)?)?;
+    let files = metadata
+        .files
+        .iter()
+        .map(InstalledMod::try_from)
+        .collect::<Result<_>>()?;
+
+    Ok(ResolvedModpack {
+        modpack_id: id.into(),
+        version_id,
+        version_name,
+        minecraft_version: game_version.into(),
+        loader: loader.modrinth_name().into(),
+        loader_version: profile.loader_version.map(|v| v.id),
+        files,
+    })
+}
+
+/// Downloads the files of `manifest` that are missing from the profile or
+/// don't match their hash, leaving everything else untouched
+pub async fn repair(
+    profile_dir: &Path,
+    manifest: &Manifest,
+    show_progress: bool,
+) -> Result<()> {
+    let mut to_download = Vec::new();
+    for installed in find_broken(profile_dir, manifest, show_progress).await? {
+        warn!("{} is missing or corrupted", installed.filename());
+        to_download.push(Downloadable {
+            download_url: installed.url,
+            output: installed.path,
+            length: installed.size,
+        });
+    }
+
+    if to_download.is_empty() {
+        info!("Nothing to repair!");
+    } else {
+        let count = to_download.len();
+        download(
+            profile_dir.into(),
+            to_download,
+            Vec::new(),
+            false,
+            None,
+            HashMap::new(),
+            false,
+        )
+        .await?;
+        info!("Repaired {count} files");
+    }
+
+    Ok(())
+}
+
+/// Deletes the installed mods of a profile so that the next install starts
+/// from scratch, saves and other user files are left alone. A mods folder
+/// outside of the profile is kept, only the mods of the manifest are deleted.
+pub async fn wipe(
+    profile_dir: &Path,
+    mods_dir: &Path,
+    reset_config: bool,
+) -> Result<()> {
+    let mut to_remove = Vec::new();
+    if mods_dir.starts_with(profile_dir) {
+        to_remove.push(mods_dir.to_path_buf());
+    } else if let Ok(manifest) = Manifest::load(profile_dir) {
+        for installed in manifest.mods {
+            let path = profile_dir.join(&installed.path);
+            if path.starts_with(mods_dir) && path.is_file() {
+                remove_file(path).await?;
+            }
+        }
+    }
+    if reset_config {
+        to_remove.push(profile_dir.join("config"));
+    }
+
+    for dir in to_remove {
+        if dir.try_exists()? {
+            info!("Deleting {dir:?}");
+            remove_dir_all(dir).await?;
+        }
+    }
+    let manifest = profile_dir.join(MANIFEST_FILE);
+    if manifest.try_exists()? {
+        remove_file(manifest).await?;
+    }
+
+    Ok(())
+}
+
+/// Returns the files of `manifest` that are missing from the profile or don't
+/// match their hash, hashing them on all cores
+async fn find_broken(
+    profile_dir: &Path,
+    manifest: &Manifest,
+    show_progress: bool,
+) -> Result<Vec<InstalledMod>> {
+    let progress_bar = if show_progress {
+        ProgressBar::new(manifest.mods.len() as u64)
+            .with_style(STYLE_COUNT.clone())
+    } else {
+        ProgressBar::hidden()
+    };
+    let threads = available_parallelism().map_or(1, NonZeroUsize::get);
+    let semaphore = Arc::new(Semaphore::new(threads));
+
+    let mut tasks = JoinSet::new();
+    for installed in manifest.mods.iter().cloned() {
+        let permit = semaphore.clone().acquire_owned().await?;
+        let path = profile_dir.join(&installed.path);
+        let progress_bar = progress_bar.clone();
+        tasks.spawn(async move {
+            let _permit = permit;
+            let intact = path.is_file()
+                && spawn_blocking(move || sha1_file(&path)).await??
+                    == installed.hashes.sha1;
+            progress_bar.inc(1);
+            Ok::<_, UklientError>((!intact).then_some(installed))
+        });
+    }
+
+    let mut broken = Vec::new();
+    while let Some(res) = tasks.join_next().await {
+        broken.extend(res??);
+    }
+    progress_bar.finish_and_clear();
+
+    Ok(broken)
+}
+
+fn sha1_file(path: &Path) -> Result<String> {
+    let content = std::fs::read(path)?;
+    Ok(sha1_smol::Sha1::from(content).digest().to_string())
+}
+
+/// Which sides a modpack file supports
+#[derive(Debug, Clone, Deserialize)]
+struct FileEnvironment {
+    client: ProjectSupportRange,
+    #[allow(dead_code)]
+    server: ProjectSupportRange,
+}
+
+impl FileEnvironment {
+    fn of(file: &ModpackFile) -> Result<Option<Self>> {
+        // libium doesn't make the environment public, go through its json form
+        file.env
+            .as_ref()
+            .map(|env| Ok(serde_json::from_value(serde_json::to_value(env)?)?))
+            .transpose()
     }
 }
 
+/// Whether `file` is the mod designated by `name_or_id`, which is either its
+/// Modrinth project id or its name, the start of its filename before the
+/// version
+fn matches_mod(file: &Downloadable, name_or_id: &str) -> bool {
+    if modrinth_ids(&file.download_url).map(|(project, _)| project)
+        == Some(name_or_id)
+    {
+        return true;
+    }
+
+    let wanted = name_or_id.to_lowercase();
+    let name = mod_name(&file.filename());
+    name == wanted
+        || ["-fabric", "-quilt", "-forge", "-neoforge"]
+            .iter()
+            .any(|loader| name.strip_suffix(loader) == Some(wanted.as_str()))
+}
+
+/// The name of a mod from its filename, e.g. `sodium-extra` for
+/// `sodium-extra-0.4.16+mc1.19.3-build.96.jar`
+fn mod_name(filename: &str) -> String {
+    let filename = filename.to_lowercase();
+    let stem = filename.strip_suffix(".jar").unwrap_or(&filename);
+    // the version starts after a separator, with a digit, `v1` or `mc1`
+    let is_version = |rest: &str| {
+        let rest = rest
+            .strip_prefix("mc")
+            .or_else(|| rest.strip_prefix('v'))
+            .unwrap_or(rest);
+        rest.starts_with(|c: char| c.is_ascii_digit())
+    };
+    let end = stem
+        .char_indices()
+        .find(|&(i, c)| {
+            matches!(c, '-' | '_' | '+') && is_version(&stem[i + 1..])
+        })
+        .map_or(stem.len(), |(i, _)| i);
+    stem[..end].into()
+}
+
+/// Reads the mods listed in the denylist file, one per line, ignoring empty
+/// lines and `#` comments
+pub fn read_denylist() -> Result<Vec<String>> {
+    let path = denylist_path();
+    if !path.try_exists()? {
+        return Ok(Vec::new());
+    }
+
+    Ok(std::fs::read_to_string(path)?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(String::from)
+        .collect())
+}
+
+/// Copies the files of the patches directory of `modpack_id` over the
+/// profile, or only lists them with `dry_run`
+pub fn apply_patches(
+    modpack_id: &str,
+    profile_dir: &Path,
+    dry_run: bool,
+) -> Result<()> {
+    let dir = patches_dir(modpack_id)?;
+    if !dir.try_exists()? {
+        if dry_run {
+            info!("There are no patches in {}", dir.display());
+        }
+        return Ok(());
+    }
+
+    for relative in patch_files(&dir, Path::new(""))? {
+        if dry_run {
+            info!("Would copy {}", relative.display());
+            continue;
+        }
+        let destination = profile_dir.join(&relative);
+        if let Some(parent) = destination.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::copy(dir.join(&relative), destination)?;
+        info!("Applied patch {}", relative.display());
+    }
+
+    Ok(())
+}
+
+/// Lists the files under `root/relative`, relative to `root`
+fn patch_files(root: &Path, relative: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in read_dir(root.join(relative))? {
+        let entry = entry?;
+        let path = relative.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            files.extend(patch_files(root, &path)?);
+        } else {
+            files.push(path);
+        }
+    }
+
+    Ok(files)
+}
+
+/// Extracts the project and version ids from a
+/// `cdn.modrinth.com/data/<project>/versions/<version>/<file>` url
+pub fn modrinth_ids(url: &Url) -> Option<(&str, &str)> {
+    let segments: Vec<&str> = url.path_segments()?.collect();
+    match segments.as_slice() {
+        ["data", project, "versions", version, ..] => Some((project, version)),
+        _ => None,
+    }
+}
+
+/// Keeps the `wanted` mods and the mods they require, resolving the
+/// dependencies with the Modrinth versions the files come from
+async fn keep_only(
+    modrinth: &Modrinth,
+    files: Vec<Downloadable>,
+    wanted: &[String],
+) -> Result<Vec<Downloadable>> {
+    let version_ids = files
+        .iter()
+        .filter_map(|f| modrinth_ids(&f.download_url))
+        .map(|(_, version)| version)
+        .collect_vec();
+    let versions = modrinth.get_multiple_versions(&version_ids).await?;
+
+    let mut required = files
+        .iter()
+        .filter(|f| wanted.iter().any(|w| matches_mod(f, w)))
+        .filter_map(|f| modrinth_ids(&f.download_url))
+        .map(|(project, _)| project.to_string())
+        .collect::<HashSet<_>>();
+
+    let mut queue = required.iter().cloned().collect_vec();
+    while let Some(project) = queue.pop() {
+        let dependencies = versions
+            .iter()
+            .filter(|v| v.project_id == project)
+            .flat_map(|v| &v.dependencies)
+            .filter(|d| d.dependency_type == DependencyType::Required)
+            .filter_map(|d| d.project_id.clone());
+        for dependency in dependencies {
+            if required.insert(dependency.clone()) {
+                queue.push(dependency);
+            }
+        }
+    }
+
+    Ok(files
+        .into_iter()
+        .filter(|file| {
+            let kept = wanted.iter().any(|w| matches_mod(file, w))
+                || modrinth_ids(&file.download_url)
+                    .filter(|(project, _)| required.contains(*project))
+                    .is_some();
+            if !kept {
+                info!("Skipping {}", file.filename());
+            }
+            kept
+        })
+        .collect())
+}
+
 fn read_overrides(directory: &Path) -> Result<Vec<(OsString, PathBuf)>> {
     let mut to_install = Vec::new();
+    if !directory.is_dir() {
+        return Ok(to_install);
+    }
+
     for file in read_dir(directory)? {
         let file = file?;
         to_install.push((file.file_name(), file.path()));
@@ -157,10 +1172,13 @@ fn read_overrides(directory: &Path) -> Result<Vec<(OsString, PathBuf)>> {
     Ok(to_install)
 }
 
+/// Skips the files of `directory` that are already there and moves the others
+/// to `.old`, or only those in `owned` if it is given
 async fn clean(
     directory: &Path,
     to_download: &mut Vec<Downloadable>,
     to_install: &mut Vec<(OsString, PathBuf)>,
+    owned: Option<&HashSet<PathBuf>>,
 ) -> Result<()> {
     let dupes = find_dupes_by_key(to_download, Downloadable::filename);
     if !dupes.is_empty() {
@@ -194,8 +1212,11 @@ async fn clean(
             {
                 // Don't install it
                 to_install.swap_remove(index);
-            // Or else, move the file to `directory`/.old
-            // If the file is a `.part` file or if the move failed, delete the file
+            // Files uklient didn't install in a shared directory are kept
+            } else if owned.map_or(false, |owned| !owned.contains(&file.path()))
+            {
+                // Or else, move the file to `directory`/.old
+                // If the file is a `.part` file or if the move failed, delete the file
             } else if filename.ends_with("part")
                 || move_file(
                     file.path(),
@@ -215,30 +1236,75 @@ async fn download(
     output_dir: PathBuf,
     to_download: Vec<Downloadable>,
     to_install: Vec<(OsString, PathBuf)>,
-) -> Result<()> {
+    force_overrides: bool,
+    store: Option<HashMap<Url, String>>,
+    mut mirrors: HashMap<Url, Vec<Url>>,
+    best_effort: bool,
+) -> Result<Vec<String>> {
     create_dir_all(&*output_dir).await?;
     let mut tasks = JoinSet::new();
     let semaphore = Arc::new(Semaphore::new(75));
     let output_dir = Arc::new(output_dir);
+    let total = to_download.len();
     for downloadable in to_download {
         let permit = semaphore.clone().acquire_owned().await?;
         let output_dir = output_dir.clone();
+        let sha1 = store
+            .as_ref()
+            .and_then(|store| store.get(&downloadable.download_url))
+            .cloned();
+        let mirrors = mirrors
+            .remove(&downloadable.download_url)
+            .unwrap_or_default();
         tasks.spawn(async move {
             let _permit = permit;
-            info!("Downloading {}", downloadable.filename());
-            downloadable.download(&CLIENT, &output_dir, |_| {}).await?;
-            Ok::<(), UklientError>(())
+            let res = match sha1 {
+                Some(sha1) => {
+                    link_from_store(&downloadable, &output_dir, &sha1, &mirrors)
+                        .await
+                }
+                None => {
+                    info!("Downloading {}", downloadable.filename());
+                    http::download_with_mirrors(
+                        &downloadable,
+                        &output_dir,
+                        &mirrors,
+                    )
+                    .await
+                }
+            };
+            res.map_err(|e| (downloadable.filename(), e))
         });
     }
+    let mut failed = Vec::new();
+    let mut done = 0;
     while let Some(res) = tasks.join_next().await {
-        res??;
+        done += 1;
+        ipc::emit(Event::Progress { done, total });
+        match res? {
+            Ok(()) => {}
+            Err((filename, e)) if best_effort => {
+                warn!("Could not download {filename}: {e}");
+                failed.push(filename);
+            }
+            Err((_, e)) => return Err(e),
+        }
     }
     for installable in to_install {
         if installable.1.is_file() {
-            copy(installable.1, output_dir.join(&installable.0)).await?;
+            let destination = output_dir.join(&installable.0);
+            if destination.exists() && !force_overrides {
+                info!(
+                    "Kept existing {}, use --force-overrides to replace it",
+                    installable.0.to_string_lossy()
+                );
+                continue;
+            }
+            copy(installable.1, destination).await?;
         } else if installable.1.is_dir() {
             let mut copy_options = DirCopyOptions::new();
-            copy_options.overwrite = true;
+            copy_options.overwrite = force_overrides;
+            copy_options.skip_exist = !force_overrides;
             copy_dir(installable.1, &*output_dir, &copy_options)?;
         } else {
             return Err(UnknownTypeError(installable.0));
@@ -246,6 +1312,67 @@ async fn download(
         info!("Installed {}", installable.0.to_string_lossy());
     }
 
+    Ok(failed)
+}
+
+/// Links `downloadable` into `output_dir` from the store of files shared by
+/// all profiles, downloading it there first if needed. Falls back to copying
+/// when linking isn't possible, e.g. across filesystems
+async fn link_from_store(
+    downloadable: &Downloadable,
+    output_dir: &Path,
+    sha1: &str,
+    mirrors: &[Url],
+) -> Result<()> {
+    // the hash comes from the index and names the stored file
+    if sha1.len() != 40 || !sha1.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(UklientError::InvalidHash {
+            file: downloadable.filename(),
+            hash: sha1.into(),
+        });
+    }
+    let store_dir = cache_dir().join("store").join(&sha1[..2]);
+    let stored = store_dir.join(sha1);
+    if !stored.is_file() {
+        info!("Downloading {}", downloadable.filename());
+        create_dir_all(&store_dir).await?;
+        let mut to_store = downloadable.clone();
+        to_store.output = sha1.into();
+        http::download_with_mirrors(&to_store, &store_dir, mirrors).await?;
+
+        let path = stored.clone();
+        if spawn_blocking(move || sha1_file(&path)).await?? != sha1 {
+            remove_file(&stored).await?;
+            return Err(UklientError::ChecksumMismatch(
+                downloadable.filename(),
+            ));
+        }
+    }
+
+    let destination = output_dir.join(&downloadable.output);
+    if let Some(parent) = destination.parent() {
+        create_dir_all(parent).await?;
+    }
+    if hard_link(&stored, &destination).await.is_err() {
+        debug!("Could not link {}, copying it", downloadable.filename());
+        copy(&stored, &destination).await?;
+    }
+
+    Ok(())
+}
+
+/// Removes the `.part` files left over by interrupted downloads
+pub async fn remove_partial_files(directory: &Path) -> Result<()> {
+    if !directory.is_dir() {
+        return Ok(());
+    }
+
+    for file in read_dir(directory)? {
+        let path = file?.path();
+        if path.extension().filter(|&ext| ext == "part").is_some() {
+            remove_file(path).await?;
+        }
+    }
     Ok(())
 }
 
@@ -267,3 +1394,410 @@ where
     indices.reverse();
     indices
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(filename: &str, project: &str) -> Downloadable {
+        versioned(filename, project, "abcdefgh")
+    }
+
+    fn versioned(filename: &str, project: &str, version: &str) -> Downloadable {
+        Downloadable {
+            download_url: Url::parse(&format!(
+                "https://cdn.modrinth.com/data/{project}/versions/{version}/{filename}"
+            ))
+            .unwrap(),
+            output: PathBuf::from("mods").join(filename),
+            length: 0,
+        }
+    }
+
+    #[test]
+    fn mods_are_matched_by_name() {
+        let sodium =
+            file("sodium-fabric-mc1.19.3-0.4.9+build.23.jar", "AANobbMI");
+        let extra =
+            file("sodium-extra-0.4.16+mc1.19.3-build.96.jar", "PtjYWJkn");
+        let reeses =
+            file("reeses-sodium-options-mc1.19.3-1.4.9+git.jar", "Bh37bMuy");
+        let api = file("fabric-api-0.73.2+1.19.3.jar", "P7dR8mSH");
+
+        assert!(matches_mod(&sodium, "sodium"));
+        assert!(matches_mod(&sodium, "Sodium-Fabric"));
+        assert!(!matches_mod(&extra, "sodium"));
+        assert!(!matches_mod(&reeses, "sodium"));
+        assert!(matches_mod(&extra, "sodium-extra"));
+        assert!(matches_mod(&api, "fabric-api"));
+        assert!(!matches_mod(&api, "api"));
+    }
+
+    #[test]
+    fn mods_are_matched_by_project_id() {
+        let api = file("fabric-api-0.73.2+1.19.3.jar", "P7dR8mSH");
+
+        assert!(matches_mod(&api, "P7dR8mSH"));
+        assert!(!matches_mod(&api, "P7dR8"));
+    }
+
+    #[test]
+    fn mod_names_stop_at_the_version() {
+        assert_eq!(mod_name("iris-mc1.19.3-1.5.2.jar"), "iris");
+        assert_eq!(mod_name("lazydfu-0.1.3.jar"), "lazydfu");
+        assert_eq!(mod_name("Indium_v1.0.jar"), "indium");
+        assert_eq!(mod_name("no-version.jar"), "no-version");
+    }
+
+    #[tokio::test]
+    async fn shared_mods_folders_keep_foreign_files() {
+        let dir = std::env::temp_dir()
+            .join(format!("uklient-clean-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for name in ["kept.jar", "old.jar", "mine.jar"] {
+            std::fs::write(dir.join(name), name).unwrap();
+        }
+
+        let mut to_download = vec![file("kept.jar", "AAAAAAAA")];
+        let owned = HashSet::from([dir.join("old.jar"), dir.join("kept.jar")]);
+        clean(&dir, &mut to_download, &mut Vec::new(), Some(&owned))
+            .await
+            .unwrap();
+
+        assert!(to_download.is_empty());
+        assert!(dir.join("kept.jar").is_file());
+        assert!(dir.join("mine.jar").is_file());
+        assert!(!dir.join("old.jar").exists());
+        assert!(dir.join(".old").join("old.jar").is_file());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn prune_deletes_the_dropped_mods() {
+        let dir = std::env::temp_dir()
+            .join(format!("uklient-prune-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("mods").join(".old")).unwrap();
+        std::fs::write(dir.join("mods").join("kept.jar"), "").unwrap();
+        std::fs::write(dir.join("mods").join(".old").join("dropped.jar"), "")
+            .unwrap();
+
+        let kept = installed(file("kept.jar", "AAAAAAAA"));
+        let dropped = installed(file("dropped.jar", "BBBBBBBB"));
+        prune(&dir, &[kept.clone(), dropped], &[kept])
+            .await
+            .unwrap();
+
+        assert!(dir.join("mods").join("kept.jar").is_file());
+        assert!(!dir.join("mods").join(".old").join("dropped.jar").exists());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn invalid_hashes_are_not_stored() {
+        for hash in ["", "a", "../../../../etc/passwd", &"g".repeat(40)] {
+            let result = link_from_store(
+                &file("sodium.jar", "AANobbMI"),
+                &std::env::temp_dir(),
+                hash,
+                &[],
+            )
+            .await;
+            assert!(matches!(result, Err(UklientError::InvalidHash { .. })));
+        }
+    }
+
+    fn installed(file: Downloadable) -> InstalledMod {
+        InstalledMod {
+            path: file.output,
+            hashes: crate::manifest::Hashes {
+                sha1: String::new(),
+                sha512: String::new(),
+            },
+            url: file.download_url,
+            size: 0,
+        }
+    }
+
+    fn urls(files: &[Downloadable]) -> Vec<&str> {
+        files.iter().map(|f| f.download_url.as_str()).collect()
+    }
+
+    #[test]
+    fn pinned_versions_replace_the_index() {
+        let files = vec![
+            versioned("sodium-0.4.10.jar", "AANobbMI", "sodium10"),
+            versioned("lithium-0.11.jar", "gvQqBUqZ", "lithium1"),
+            versioned("iris-1.5.jar", "YL57xq9U", "iris1500"),
+        ];
+        let pinned = [
+            installed(versioned("sodium-0.4.9.jar", "AANobbMI", "sodium09")),
+            installed(versioned("lithium-0.11.jar", "gvQqBUqZ", "lithium1")),
+            installed(versioned("indium-1.0.jar", "Orvt0mRa", "indium10")),
+        ];
+
+        let result = pin(files.clone(), &pinned);
+        assert_eq!(result[0].download_url, pinned[0].url);
+        assert_eq!(result[0].output, pinned[0].path);
+        assert_eq!(urls(&result)[1..], urls(&files)[1..]);
+    }
+
+    fn version_json(id: &str, project: &str, published: &str) -> String {
+        format!(
+            r#"{{
+                "name": "{id}",
+                "version_number": "{id}",
+                "changelog": null,
+                "dependencies": [],
+                "game_versions": ["1.19.3"],
+                "version_type": "release",
+                "loaders": ["fabric"],
+                "featured": false,
+                "id": "{id}",
+                "project_id": "{project}",
+                "author_id": "author00",
+                "date_published": "{published}",
+                "downloads": 0,
+                "changelog_url": null,
+                "files": []
+            }}"#
+        )
+    }
+
+    #[tokio::test]
+    async fn mods_published_before_since_are_held_back() {
+        use wiremock::matchers::{method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        let body = format!(
+            "[{}, {}]",
+            version_json("sodium10", "AANobbMI", "2023-01-10T00:00:00Z"),
+            version_json("lithium2", "gvQqBUqZ", "2023-03-10T00:00:00Z"),
+        );
+        Mock::given(method("GET"))
+            .and(path("/versions"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_raw(body, "application/json"),
+            )
+            .mount(&server)
+            .await;
+
+        let files = vec![
+            versioned("sodium-0.4.10.jar", "AANobbMI", "sodium10"),
+            versioned("lithium-0.12.jar", "gvQqBUqZ", "lithium2"),
+            versioned("iris-1.5.jar", "YL57xq9U", "iris1500"),
+        ];
+        let old_sodium = versioned("sodium-0.4.9.jar", "AANobbMI", "sodium09");
+        let previous = [
+            installed(old_sodium.clone()),
+            installed(versioned("lithium-0.11.jar", "gvQqBUqZ", "lithium1")),
+        ];
+        let since = "2023-02-01T00:00:00Z".parse().unwrap();
+
+        let modrinth = Modrinth::new(&server.uri()).unwrap();
+        let held = hold_back(&modrinth, files.clone(), since, &previous)
+            .await
+            .unwrap();
+
+        assert_eq!(urls(&held)[0], old_sodium.download_url.as_str());
+        assert_eq!(urls(&held)[1..], urls(&files)[1..]);
+    }
+
+    fn optional_file(filename: &str) -> ModpackFile {
+        serde_json::from_value(serde_json::json!({
+            "path": format!("mods/{filename}"),
+            "hashes": { "sha1": "", "sha512": "" },
+            "env": { "client": "optional", "server": "optional" },
+            "downloads": [format!("https://example.com/{filename}")],
+            "fileSize": 0,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn excluded_optional_files_stay_excluded() {
+        let mut optional =
+            vec![optional_file("zoom.jar"), optional_file("shaders.zip")];
+        let options = InstallOptions {
+            excluded_optional: vec!["shaders.zip".into()],
+            ..Default::default()
+        };
+
+        let excluded = exclude_optional(&mut optional, &options).unwrap();
+        assert_eq!(excluded, ["shaders.zip"]);
+        assert_eq!(optional.len(), 1);
+        assert_eq!(optional[0].path, PathBuf::from("mods/zoom.jar"));
+    }
+
+    #[test]
+    fn no_optional_excludes_everything() {
+        let mut optional =
+            vec![optional_file("zoom.jar"), optional_file("shaders.zip")];
+        let options = InstallOptions {
+            no_optional: true,
+            ..Default::default()
+        };
+
+        let excluded = exclude_optional(&mut optional, &options).unwrap();
+        assert_eq!(excluded, ["zoom.jar", "shaders.zip"]);
+        assert!(optional.is_empty());
+    }
+
+    #[test]
+    fn malformed_index_says_where() {
+        let index = r#"{
+            "formatVersion": 1,
+            "game": "minecraft",
+            "versionId": "1.0.0",
+            "name": "pack",
+            "files": [{ "path": "mods/a.jar", "downloads": [] }],
+            "dependencies": { "minecraft": "1.19.3" }
+        }"#;
+
+        let Err(UklientError::InvalidIndex { path, .. }) = parse_index(index)
+        else {
+            panic!("the index is missing fields");
+        };
+        assert_eq!(path, "files[0]");
+    }
+
+    #[test]
+    fn files_without_downloads_are_rejected() {
+        let index = r#"{
+            "name": "pack",
+            "files": [
+                {
+                    "path": "mods/a.jar",
+                    "hashes": { "sha1": "", "sha512": "" },
+                    "downloads": ["https://example.com/a.jar"],
+                    "fileSize": 0
+                },
+                {
+                    "path": "mods/b.jar",
+                    "hashes": { "sha1": "", "sha512": "" },
+                    "downloads": [],
+                    "fileSize": 0
+                }
+            ]
+        }"#;
+
+        let Err(UklientError::InvalidIndex { path, .. }) = parse_index(index)
+        else {
+            panic!("b.jar can't be downloaded");
+        };
+        assert_eq!(path, "files[1].downloads");
+    }
+
+    #[test]
+    fn index_outside_of_a_zip_is_rejected() {
+        let path = std::env::temp_dir()
+            .join(format!("uklient-index-{}.mrpack", std::process::id()));
+        std::fs::write(&path, "not a zip").unwrap();
+
+        let result = read_index(&File::open(&path).unwrap());
+        std::fs::remove_file(path).unwrap();
+        assert!(matches!(result, Err(UklientError::ZipError)));
+    }
+
+    #[test]
+    fn neoforge_indexes_are_read() {
+        let index = r#"{
+            "formatVersion": 1,
+            "game": "minecraft",
+            "versionId": "1.2.0",
+            "name": "NeoForge Pack",
+            "files": [
+                {
+                    "path": "mods/sodium-neoforge-0.6.0.jar",
+                    "hashes": {
+                        "sha1": "3c2a8a9f1e8f0c1b9c0f4b4d5e6f7a8b9c0d1e2f",
+                        "sha512": "00"
+                    },
+                    "env": { "client": "required", "server": "unsupported" },
+                    "downloads": [
+                        "https://cdn.modrinth.com/data/AANobbMI/versions/OihdIimA/sodium-neoforge-0.6.0.jar"
+                    ],
+                    "fileSize": 1024
+                }
+            ],
+            "dependencies": { "minecraft": "1.21.1", "neoforge": "21.1.66" }
+        }"#;
+
+        let index = parse_index(index).unwrap();
+        assert_eq!(index.name, "NeoForge Pack");
+        assert_eq!(index.files.len(), 1);
+    }
+
+    fn installed_profile(
+        loader: ModLoader,
+        game_version: &str,
+    ) -> ProfileMetadata {
+        ProfileMetadata {
+            name: "pack".into(),
+            loader,
+            loader_version: Some(LoaderVersion {
+                id: "47.2.0".into(),
+                url: "https://meta.modrinth.com/forge/v0/versions/47.2.0.json"
+                    .into(),
+                stable: true,
+            }),
+            game_version: game_version.into(),
+            format_version: 1,
+            icon: None,
+        }
+    }
+
+    #[test]
+    fn forge_and_neoforge_are_launched_as_forge() {
+        assert_eq!(mod_loader(Loader::Forge), ModLoader::Forge);
+        assert_eq!(mod_loader(Loader::NeoForge), ModLoader::Forge);
+        assert_eq!(mod_loader(Loader::LegacyFabric), ModLoader::Fabric);
+    }
+
+    #[test]
+    fn installed_forge_is_not_reused_for_neoforge() {
+        let installed = installed_profile(ModLoader::Forge, "1.20.1");
+
+        let reused = LoaderInfo::reuse(
+            Loader::Forge,
+            &installed,
+            Loader::Forge,
+            "1.20.1",
+        )
+        .unwrap();
+        assert_eq!(reused.kind, Loader::Forge);
+        assert_eq!(reused.version.id, "47.2.0");
+
+        let reused = LoaderInfo::reuse(
+            Loader::NeoForge,
+            &installed,
+            Loader::NeoForge,
+            "1.20.1",
+        )
+        .unwrap();
+        assert_eq!(reused.kind, Loader::NeoForge);
+
+        assert!(LoaderInfo::reuse(
+            Loader::Forge,
+            &installed,
+            Loader::NeoForge,
+            "1.20.1"
+        )
+        .is_none());
+        assert!(LoaderInfo::reuse(
+            Loader::NeoForge,
+            &installed,
+            Loader::Forge,
+            "1.20.1"
+        )
+        .is_none());
+        assert!(LoaderInfo::reuse(
+            Loader::Forge,
+            &installed,
+            Loader::Forge,
+            "1.20.4"
+        )
+        .is_none());
+    }
+}
@@ -0,0 +1,106 @@
+use crate::{http, Result, CLIENT, USER_AGENT};
+use ferinth::structures::project::Project;
+use ferinth::structures::version::Version;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use url::Url;
+
+pub const DEFAULT_API_URL: &str = "https://api.modrinth.com/v2/";
+
+/// The few Modrinth API calls uklient needs, against a configurable host
+/// (ferinth always uses the production API)
+#[derive(Debug, Clone)]
+pub struct Modrinth {
+    base_url: Url,
+}
+
+impl Modrinth {
+    pub fn new(base_url: &str) -> Result<Self> {
+        let mut base_url = Url::parse(base_url)?;
+        // without it, joining paths would replace the last segment
+        if !base_url.path().ends_with('/') {
+            base_url.set_path(&format!("{}/", base_url.path()));
+        }
+
+        Ok(Self { base_url })
+    }
+
+    pub async fn get_project(&self, id: &str) -> Result<Project> {
+        self.get(&format!("project/{id}"), &[]).await
+    }
+
+    pub async fn list_versions(
+        &self,
+        id: &str,
+        game_version: Option<&str>,
+    ) -> Result<Vec<Version>> {
+        let game_versions = game_version
+            .map(|v| serde_json::to_string(&[v]))
+            .transpose()?;
+        let query = match &game_versions {
+            Some(versions) => vec![("game_versions", versions.as_str())],
+            None => vec![],
+        };
+
+        self.get(&format!("project/{id}/version"), &query).await
+    }
+
+    pub async fn get_multiple_versions(
+        &self,
+        ids: &[&str],
+    ) -> Result<Vec<Version>> {
+        let ids = serde_json::to_string(ids)?;
+        self.get("versions", &[("ids", &ids)]).await
+    }
+
+    pub async fn search_modpacks(&self, query: &str) -> Result<Vec<SearchHit>> {
+        let response: SearchResponse = self
+            .get(
+                "search",
+                &[
+                    ("query", query),
+                    ("facets", r#"[["project_type:modpack"]]"#),
+                    ("limit", "5"),
+                ],
+            )
+            .await?;
+
+        Ok(response.hits)
+    }
+
+    async fn get<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        query: &[(&str, &str)],
+    ) -> Result<T> {
+        let url = self.base_url.join(path)?;
+        let response = http::send(|| {
+            CLIENT
+                .get(url.clone())
+                .query(query)
+                .header(reqwest::header::USER_AGENT, USER_AGENT)
+        })
+        .await?;
+
+        Ok(response.json().await?)
+    }
+}
+
+impl Default for Modrinth {
+    fn default() -> Self {
+        Self {
+            base_url: Url::parse(DEFAULT_API_URL).unwrap(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct SearchResponse {
+    hits: Vec<SearchHit>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SearchHit {
+    pub slug: String,
+    pub title: String,
+}
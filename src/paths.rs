@@ -0,0 +1,174 @@
+use crate::{Result, UklientError};
+use libium::HOME;
+use once_cell::sync::OnceCell;
+use std::path::PathBuf;
+use std::process::Command;
+
+static DATA_DIR: OnceCell<PathBuf> = OnceCell::new();
+
+/// Where the profiles are installed
+pub fn data_dir() -> PathBuf {
+    if let Some(dir) = DATA_DIR.get() {
+        return dir.clone();
+    }
+
+    xdg_or_legacy(xdg_dir("XDG_DATA_HOME"), HOME.join(".uklient"))
+}
+
+/// Installs the profiles in `dir` instead, for the rest of the run
+pub fn set_data_dir(dir: PathBuf) {
+    let _ = DATA_DIR.set(dir);
+}
+
+/// Where downloaded modpacks are cached
+pub fn cache_dir() -> PathBuf {
+    xdg_dir("XDG_CACHE_HOME").unwrap_or_else(|| legacy_dir().join(".cache"))
+}
+
+/// Where modpacks and Java archives are extracted while installing
+pub fn tmp_dir() -> PathBuf {
+    xdg_dir("XDG_CACHE_HOME")
+        .map(|dir| dir.join("tmp"))
+        .unwrap_or_else(|| legacy_dir().join(".tmp"))
+}
+
+/// Where the Java runtimes are downloaded
+pub fn java_dir() -> PathBuf {
+    xdg_or_legacy(
+        xdg_dir("XDG_DATA_HOME").map(|dir| dir.join("java")),
+        legacy_dir(),
+    )
+}
+
+/// Where the launcher state, install history and pending logins are stored
+pub fn state_dir() -> PathBuf {
+    xdg_or_legacy(xdg_dir("XDG_STATE_HOME"), legacy_dir())
+}
+
+/// Where the credentials of the account used by default are stored
+pub fn credentials_path() -> PathBuf {
+    config_dir().join("credentials.json")
+}
+
+/// Where the credentials of a specific account are stored
+pub fn account_path(username: &str) -> PathBuf {
+    accounts_dir().join(format!("{username}.json"))
+}
+
+/// Where the credentials of every account that logged in are stored
+pub fn accounts_dir() -> PathBuf {
+    config_dir().join("accounts")
+}
+
+/// Where the settings are read from when no --config-path is given
+pub fn config_path() -> PathBuf {
+    config_dir().join("config.toml")
+}
+
+/// Where the mods that are never installed are listed
+pub fn denylist_path() -> PathBuf {
+    config_dir().join("denylist.txt")
+}
+
+/// Where the registry of the installed profiles is stored
+pub fn profiles_path() -> PathBuf {
+    data_dir().join("profiles.json")
+}
+
+/// Where the files copied over a modpack's profile after each install are
+pub fn patches_dir(modpack_id: &str) -> Result<PathBuf> {
+    check_modpack_id(modpack_id)?;
+    Ok(config_dir().join("patches").join(modpack_id))
+}
+
+/// Rejects the modpack ids that would point outside of the directory they
+/// are joined to
+pub fn check_modpack_id(modpack_id: &str) -> Result<()> {
+    if modpack_id.is_empty()
+        || modpack_id.contains(['/', '\\'])
+        || modpack_id.contains("..")
+    {
+        return Err(UklientError::InvalidModpackId(modpack_id.into()));
+    }
+    Ok(())
+}
+
+/// Where older versions stored the Java runtimes, temporary files and
+/// launcher state
+fn legacy_dir() -> PathBuf {
+    xdg_or_legacy(
+        xdg_dir("XDG_CONFIG_HOME"),
+        HOME.join(".config").join("uklient"),
+    )
+}
+
+/// Where the settings and accounts are stored
+fn config_dir() -> PathBuf {
+    xdg_or_legacy(xdg_dir("XDG_CONFIG_HOME"), HOME.join(".uklient"))
+}
+
+/// Whether this is the Linux binary running under the Windows Subsystem for
+/// Linux
+pub fn is_wsl() -> bool {
+    cfg!(target_os = "linux")
+        && (std::env::var_os("WSL_DISTRO_NAME").is_some()
+            || std::fs::read_to_string("/proc/sys/kernel/osrelease")
+                .map_or(false, |r| r.to_lowercase().contains("microsoft")))
+}
+
+/// The Windows user profile directory as seen from WSL, e.g.
+/// `/mnt/c/Users/name`, translated by `wslpath` so that custom mount points
+/// are respected
+pub fn windows_home() -> Option<PathBuf> {
+    let output = Command::new("cmd.exe")
+        .args(["/c", "echo %USERPROFILE%"])
+        .output()
+        .ok()?;
+    let home = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if home.is_empty() || home.contains('%') {
+        return None;
+    }
+
+    let output = Command::new("wslpath").args(["-u", &home]).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let path = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (!path.is_empty()).then(|| PathBuf::from(path))
+}
+
+/// Returns the `xdg` directory if there is one, unless only `legacy` exists,
+/// which is where older versions stored the same files
+fn xdg_or_legacy(xdg: Option<PathBuf>, legacy: PathBuf) -> PathBuf {
+    match xdg {
+        Some(dir) if dir.exists() || !legacy.exists() => dir,
+        _ => legacy,
+    }
+}
+
+/// Returns `$VAR/uklient` on Linux if the XDG variable is set
+fn xdg_dir(var: &str) -> Option<PathBuf> {
+    if !cfg!(target_os = "linux") {
+        return None;
+    }
+
+    std::env::var_os(var)
+        .filter(|dir| !dir.is_empty())
+        .map(|dir| PathBuf::from(dir).join("uklient"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn modpack_ids_stay_in_their_directory() {
+        assert!(patches_dir("ukupvp").unwrap().ends_with("patches/ukupvp"));
+        for id in ["../config", "a/b", "a\\b", "..", ""] {
+            assert!(matches!(
+                patches_dir(id),
+                Err(UklientError::InvalidModpackId(_))
+            ));
+        }
+    }
+}
@@ -0,0 +1,61 @@
+use crate::Result;
+use clap::ValueEnum;
+use tokio::process::Command;
+use tracing::{info, warn};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Priority {
+    Low,
+    Normal,
+    High,
+}
+
+/// Changes the OS priority of the game, only warning if it doesn't work
+pub async fn set_priority(pid: u32, priority: Priority) {
+    match try_set_priority(pid, priority).await {
+        Ok(true) => info!("Set the game priority to {priority:?}"),
+        Ok(false) => warn!("Could not set the game priority"),
+        Err(e) => warn!("Could not set the game priority: {e}"),
+    }
+}
+
+#[cfg(unix)]
+async fn try_set_priority(pid: u32, priority: Priority) -> Result<bool> {
+    let niceness = match priority {
+        Priority::Low => "10",
+        Priority::Normal => "0",
+        // needs to be root on linux
+        Priority::High => "-10",
+    };
+
+    let status = Command::new("renice")
+        .args(["-n", niceness, "-p", &pid.to_string()])
+        .status()
+        .await?;
+    Ok(status.success())
+}
+
+#[cfg(windows)]
+async fn try_set_priority(pid: u32, priority: Priority) -> Result<bool> {
+    let class = match priority {
+        Priority::Low => "BelowNormal",
+        Priority::Normal => "Normal",
+        Priority::High => "High",
+    };
+
+    let status = Command::new("powershell")
+        .args([
+            "-NoProfile",
+            "-Command",
+            &format!("(Get-Process -Id {pid}).PriorityClass = '{class}'"),
+        ])
+        .status()
+        .await?;
+    Ok(status.success())
+}
+
+#[cfg(not(any(unix, windows)))]
+async fn try_set_priority(_pid: u32, _priority: Priority) -> Result<bool> {
+    // unsupported platform
+    Ok(false)
+}
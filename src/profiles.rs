@@ -0,0 +1,246 @@
+use crate::manifest::{last_launched, Manifest, ProfileSummary, LAUNCHED_FILE};
+use crate::paths::{data_dir, profiles_path};
+use crate::{Result, UklientError};
+use fs_extra::dir::CopyOptions;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use theseus::profile;
+
+/// An installed profile, as remembered in the registry
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RegisteredProfile {
+    pub name: String,
+    pub path: PathBuf,
+    pub loader: String,
+    pub game_version: String,
+    pub modpack_id: String,
+}
+
+impl From<ProfileSummary> for RegisteredProfile {
+    fn from(summary: ProfileSummary) -> Self {
+        Self {
+            name: summary.name,
+            path: summary.path,
+            loader: summary.loader,
+            game_version: summary.game_version,
+            modpack_id: summary.id,
+        }
+    }
+}
+
+/// The profiles installed by uklient, so that they can be managed by name
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Registry {
+    pub profiles: Vec<RegisteredProfile>,
+}
+
+impl Registry {
+    /// Reads the registry, building it from the installed manifests the first
+    /// time, and forgets the profiles whose directory was deleted
+    pub fn load() -> Result<Self> {
+        let path = profiles_path();
+        let mut registry = if path.try_exists()? {
+            let file = File::open(path)?;
+            serde_json::from_reader(BufReader::new(file))?
+        } else {
+            let profiles = Manifest::list()?
+                .into_iter()
+                .map(|(_, dir, manifest)| {
+                    ProfileSummary::new(&dir, &manifest).into()
+                })
+                .collect();
+            Self { profiles }
+        };
+
+        registry.profiles.retain(|p| p.path.is_dir());
+        Ok(registry)
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let path = profiles_path();
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let file = File::create(path)?;
+        serde_json::to_writer_pretty(BufWriter::new(file), self)?;
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Result<&RegisteredProfile> {
+        self.profiles
+            .iter()
+            .find(|p| p.name == name)
+            .ok_or_else(|| UklientError::UnknownProfile(name.into()))
+    }
+
+    /// Adds or updates the profile in `profile_dir` after an install, keeping
+    /// the name it was given
+    pub fn register(&mut self, profile_dir: &Path, manifest: &Manifest) {
+        let mut profile: RegisteredProfile =
+            ProfileSummary::new(profile_dir, manifest).into();
+        if let Some(existing) =
+            self.profiles.iter_mut().find(|p| p.path == profile_dir)
+        {
+            profile.name = std::mem::take(&mut existing.name);
+            *existing = profile;
+            return;
+        }
+
+        let base = profile.name.clone();
+        let mut n = 1;
+        while self.get(&profile.name).is_ok() {
+            n += 1;
+            profile.name = format!("{base}-{n}");
+        }
+        self.profiles.push(profile);
+    }
+
+    /// Describes the registered profiles under their registered name, from
+    /// their manifest when it can be read
+    pub fn summaries(&self) -> Vec<ProfileSummary> {
+        self.profiles
+            .iter()
+            .map(|p| match Manifest::load(&p.path) {
+                Ok(manifest) => ProfileSummary {
+                    name: p.name.clone(),
+                    ..ProfileSummary::new(&p.path, &manifest)
+                },
+                Err(_) => ProfileSummary {
+                    name: p.name.clone(),
+                    id: p.modpack_id.clone(),
+                    game_version: p.game_version.clone(),
+                    loader: p.loader.clone(),
+                    path: p.path.clone(),
+                    mod_count: 0,
+                    last_launched: last_launched(&p.path),
+                },
+            })
+            .collect()
+    }
+
+    pub fn forget(&mut self, profile_dir: &Path) {
+        self.profiles.retain(|p| p.path != profile_dir);
+    }
+
+    /// Renames a profile, in the registry and in theseus
+    pub async fn rename(&mut self, name: &str, new_name: &str) -> Result<()> {
+        self.check_free(new_name)?;
+        let path = self.get(name)?.path.clone();
+
+        profile::edit(&path, |p| {
+            p.metadata.name = new_name.into();
+            async { Ok(()) }
+        })
+        .await?;
+        if let Some(p) = self.profiles.iter_mut().find(|p| p.path == path) {
+            p.name = new_name.into();
+        }
+        Ok(())
+    }
+
+    /// Copies a profile to a new directory and registers the copy, returning
+    /// its directory. The manifest of the copy takes `new_name` as its id so
+    /// that installing or launching the modpack by id keeps using the
+    /// original.
+    pub async fn duplicate(
+        &mut self,
+        name: &str,
+        new_name: &str,
+    ) -> Result<PathBuf> {
+        self.check_free(new_name)?;
+        let source = self.get(name)?.clone();
+        let mut mc_profile =
+            profile::get(&source.path).await?.ok_or_else(|| {
+                UklientError::ProfileNotRegistered(source.path.clone())
+            })?;
+
+        let target = data_dir().join(new_name.replace(' ', "_"));
+        if target.try_exists()? {
+            return Err(UklientError::ProfileExists(new_name.into()));
+        }
+        std::fs::create_dir_all(&target)?;
+        let options = CopyOptions {
+            content_only: true,
+            ..CopyOptions::new()
+        };
+        fs_extra::dir::copy(&source.path, &target, &options)?;
+        let launched = target.join(LAUNCHED_FILE);
+        if launched.try_exists()? {
+            std::fs::remove_file(launched)?;
+        }
+
+        mc_profile.path = target.clone();
+        mc_profile.metadata.name = new_name.into();
+        profile::add(mc_profile).await?;
+
+        let profile = match Manifest::load(&target) {
+            Ok(mut manifest) => {
+                manifest.modpack_id = new_name.into();
+                manifest.save(&target)?;
+                ProfileSummary::new(&target, &manifest).into()
+            }
+            Err(_) => RegisteredProfile {
+                path: target.clone(),
+                ..source
+            },
+        };
+        self.profiles.push(RegisteredProfile {
+            name: new_name.into(),
+            ..profile
+        });
+        Ok(target)
+    }
+
+    fn check_free(&self, name: &str) -> Result<()> {
+        if self.get(name).is_ok() {
+            return Err(UklientError::ProfileExists(name.into()));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use itertools::Itertools;
+
+    fn manifest(modpack_id: &str) -> Manifest {
+        Manifest {
+            modpack_id: modpack_id.into(),
+            version_id: "version".into(),
+            minecraft_version: "1.19.3".into(),
+            loader: "fabric".into(),
+            loader_version: None,
+            mods: Vec::new(),
+            installed_at: None,
+            excluded_optional: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn registered_names_are_unique() {
+        let mut registry = Registry::default();
+        registry.register(Path::new("/a/pack"), &manifest("one"));
+        registry.register(Path::new("/b/pack"), &manifest("two"));
+        registry.register(Path::new("/a/pack"), &manifest("three"));
+
+        let names = registry.profiles.iter().map(|p| &p.name).collect_vec();
+        assert_eq!(names, ["pack", "pack-2"]);
+        assert_eq!(registry.get("pack").unwrap().modpack_id, "three");
+        assert_eq!(registry.get("pack-2").unwrap().modpack_id, "two");
+    }
+
+    #[test]
+    fn summaries_keep_the_registered_name() {
+        let mut registry = Registry::default();
+        registry.register(Path::new("/nowhere/pack"), &manifest("one"));
+        registry.profiles[0].name = "renamed".into();
+
+        let summaries = registry.summaries();
+        assert_eq!(summaries[0].name, "renamed");
+        assert_eq!(summaries[0].id, "one");
+    }
+}
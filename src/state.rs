@@ -0,0 +1,40 @@
+use crate::paths::state_dir;
+use crate::Result;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::PathBuf;
+
+/// What uklient remembers between runs
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct State {
+    /// The last modpack that was successfully installed
+    pub last_modpack_id: Option<String>,
+}
+
+impl State {
+    pub fn load() -> Result<Self> {
+        let path = state_path();
+        if !path.try_exists()? {
+            return Ok(Self::default());
+        }
+
+        let file = File::open(path)?;
+        Ok(serde_json::from_reader(BufReader::new(file))?)
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let path = state_path();
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let file = File::create(path)?;
+        serde_json::to_writer(BufWriter::new(file), self)?;
+        Ok(())
+    }
+}
+
+fn state_path() -> PathBuf {
+    state_dir().join("state.json")
+}
@@ -0,0 +1,28 @@
+use crate::Result;
+use std::path::Path;
+
+/// The game option turning off the optional telemetry, added in 1.19.3.
+/// The required telemetry can't be turned off without breaking the services
+/// it is sent with (skins, chat reporting, realms)
+const OPT_IN_EXTRA: &str = "telemetryOptInExtra";
+
+/// Opts out of the optional telemetry in the `options.txt` of a profile,
+/// keeping the other options
+pub fn disable_telemetry(profile_dir: &Path) -> Result<()> {
+    let path = profile_dir.join("options.txt");
+    let options = if path.try_exists()? {
+        std::fs::read_to_string(&path)?
+    } else {
+        String::new()
+    };
+
+    let mut lines = options
+        .lines()
+        .filter(|line| line.split(':').next() != Some(OPT_IN_EXTRA))
+        .map(String::from)
+        .collect::<Vec<_>>();
+    lines.push(format!("{OPT_IN_EXTRA}:false"));
+
+    std::fs::write(path, lines.join("\n") + "\n")?;
+    Ok(())
+}
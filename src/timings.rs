@@ -0,0 +1,44 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::span::{Attributes, Id};
+use tracing::{info, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::registry::LookupSpan;
+
+/// The phases that finished, in the order they did
+static TIMINGS: Mutex<Vec<(&'static str, Duration)>> = Mutex::new(Vec::new());
+
+struct Started(Instant);
+
+/// Records how long the spans of uklient take, for `--timings`
+pub struct TimingLayer;
+
+impl<S> Layer<S> for TimingLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, _: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            if span.metadata().target().starts_with("uklient") {
+                span.extensions_mut().insert(Started(Instant::now()));
+            }
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else { return };
+        let extensions = span.extensions();
+        let Some(Started(start)) = extensions.get::<Started>() else { return };
+        if let Ok(mut timings) = TIMINGS.lock() {
+            timings.push((span.name(), start.elapsed()));
+        }
+    }
+}
+
+/// Logs how long each recorded phase took
+pub fn report() {
+    let Ok(timings) = TIMINGS.lock() else { return };
+    for (name, duration) in timings.iter() {
+        info!("{name} took {duration:.2?}");
+    }
+}
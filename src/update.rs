@@ -0,0 +1,46 @@
+use crate::{Result, CLIENT, USER_AGENT};
+use serde::Deserialize;
+use tracing::{info, warn};
+
+const LATEST_RELEASE_URL: &str =
+    "https://api.github.com/repos/uku3lig/uklient-rs/releases/latest";
+const CURRENT_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+#[derive(Debug, Deserialize)]
+struct Release {
+    tag_name: String,
+    html_url: String,
+}
+
+/// Tells the user if a newer release of uklient is available
+pub async fn check_updates() -> Result<()> {
+    let release: Release = CLIENT
+        .get(LATEST_RELEASE_URL)
+        // github refuses requests without a user agent
+        .header(reqwest::header::USER_AGENT, USER_AGENT)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+
+    if parse_version(&release.tag_name) > parse_version(CURRENT_VERSION) {
+        warn!(
+            "uklient {} is available (you have {CURRENT_VERSION}), download it at {}",
+            release.tag_name, release.html_url
+        );
+    } else {
+        info!("uklient {CURRENT_VERSION} is up to date");
+    }
+
+    Ok(())
+}
+
+/// Parses `v1.2.3` or `1.2.3-beta` into `[1, 2, 3]`
+fn parse_version(version: &str) -> Vec<u64> {
+    version
+        .trim_start_matches('v')
+        .split(['.', '-', '+'])
+        .map_while(|part| part.parse().ok())
+        .collect()
+}
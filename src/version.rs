@@ -3,6 +3,10 @@ use std::fmt::Display;
 use regex::Regex;
 use serde::{Deserialize, Serialize};
 
+/// The list of Minecraft versions published by Mojang
+pub const MOJANG_MANIFEST_URL: &str =
+    "https://piston-meta.mojang.com/mc/game/version_manifest_v2.json";
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MinecraftVersion {
     pub minor: u8,
@@ -15,6 +19,38 @@ pub enum VersionError {
     InvalidVersion(&'static str),
     #[error("Snapshots are unsupported")]
     SnapshotsAreUnsupported,
+    #[error("Minecraft {0} does not exist")]
+    UnknownVersion(String),
+    #[error("Minecraft {0} is not a release")]
+    NotARelease(String),
+}
+
+#[derive(Deserialize)]
+struct VersionManifest {
+    versions: Vec<ManifestVersion>,
+}
+
+#[derive(Deserialize)]
+struct ManifestVersion {
+    id: String,
+    #[serde(rename = "type")]
+    kind: String,
+}
+
+/// Checks that `version` is a release listed in Mojang's version manifest
+pub async fn check_release(version: &MinecraftVersion) -> crate::Result<()> {
+    let manifest: VersionManifest =
+        crate::http::send(|| crate::CLIENT.get(MOJANG_MANIFEST_URL))
+            .await?
+            .json()
+            .await?;
+
+    let id = version.to_string();
+    match manifest.versions.iter().find(|v| v.id == id) {
+        Some(v) if v.kind == "release" => Ok(()),
+        Some(_) => Err(VersionError::NotARelease(id).into()),
+        None => Err(VersionError::UnknownVersion(id).into()),
+    }
 }
 
 impl MinecraftVersion {